@@ -1,31 +1,468 @@
-use rofi_toys::clipboard;
-use rofi_toys::rofi::{RofiPlugin, RofiPluginError};
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+use rofi_toys::rofi::{RofiPlugin, RofiPluginError, RofiRowOptions};
+use rofi_toys::utils::make_table_column;
+use rofi_toys::{clipboard, file};
+
+fn format_entry(entry: &clipboard::ClipboardListItem) -> String {
+    let mut result = make_table_column(entry.kind.to_string(), 6);
+    if entry.pinned {
+        result.push_str("[pinned] ");
+    }
+    if entry.selection == clipboard::ClipboardSelection::Primary {
+        result.push_str("[primary] ");
+    }
+    result.push_str(&entry.preview);
+    result
+}
+
+// 没有来源的条目是空字符串
+fn source_app(entry: &clipboard::ClipboardListItem) -> String {
+    entry
+        .source
+        .as_ref()
+        .map(|x| x.class.clone())
+        .unwrap_or_default()
+}
+
+fn entry_options(entry: &clipboard::ClipboardListItem) -> RofiRowOptions {
+    RofiRowOptions {
+        icon: entry.thumbnail.clone(),
+    }
+}
 
 pub fn list_clipboard(rofi: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let clipboard_datas = clipboard::clipboard_list();
+    let clipboard_datas = clipboard::ClipdClient::new().list()?;
     if clipboard_datas.is_empty() {
         return Err(RofiPluginError::new("clipboard is empty").into());
     }
 
-    for (idx, entry) in clipboard_datas.iter().enumerate() {
-        rofi.add_menu_entry_with_params(&entry, set_clipboard, vec![idx.to_string()])
+    // 固定的条目显示在最前面
+    for entry in clipboard_datas.iter().filter(|e| e.pinned) {
+        rofi.add_menu_entry_with_options(
+            &format_entry(entry),
+            set_clipboard,
+            vec![entry.id.to_string()],
+            entry_options(entry),
+        )
+    }
+
+    for entry in clipboard_datas.iter().filter(|e| !e.pinned) {
+        rofi.add_menu_entry_with_options(
+            &format_entry(entry),
+            set_clipboard,
+            vec![entry.id.to_string()],
+            entry_options(entry),
+        )
     }
 
+    rofi.add_menu_entry("[search]", search_clipboard);
+    rofi.add_menu_entry("[regex_search]", regex_search_clipboard);
+    rofi.add_menu_entry("[by_app]", list_apps);
+    rofi.add_menu_entry("[manage]", manage_clipboard);
+
+    Ok(())
+}
+
+pub fn list_apps(rofi: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
+    let clipboard_datas = clipboard::ClipdClient::new().list()?;
+
+    // 按出现的顺序排列, 最近复制过的应用在前面
+    let mut apps: Vec<(String, usize)> = Vec::new();
+    for entry in clipboard_datas.iter() {
+        let app = source_app(entry);
+        match apps.iter_mut().find(|(x, _)| *x == app) {
+            Some((_, count)) => *count += 1,
+            None => apps.push((app, 1)),
+        }
+    }
+
+    for (app, count) in apps {
+        let name = if app.is_empty() { "[unknown]" } else { &app };
+        rofi.add_menu_entry_with_params(
+            &format!("{} ({})", name, count),
+            list_app_entries,
+            vec![app.clone()],
+        );
+    }
+    rofi.add_menu_entry("[back]", list_clipboard);
+
+    Ok(())
+}
+
+pub fn list_app_entries(rofi: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    let clipboard_datas = clipboard::ClipdClient::new().list()?;
+
+    for entry in clipboard_datas
+        .iter()
+        .filter(|e| source_app(e) == params[0])
+    {
+        rofi.add_menu_entry_with_options(
+            &format_entry(entry),
+            set_clipboard,
+            vec![entry.id.to_string()],
+            entry_options(entry),
+        )
+    }
+    rofi.add_menu_entry("[back]", list_apps);
+
     Ok(())
 }
 
 pub fn set_clipboard(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
-    clipboard::clipboard_set_by_idx(params[0].parse::<usize>()?);
+    clipboard::ClipdClient::new().set_by_id(
+        params[0].parse::<u64>()?,
+        clipboard::ClipboardSetTarget::Clipboard,
+    )?;
+
+    Ok(())
+}
+
+pub fn set_primary(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    clipboard::ClipdClient::new().set_by_id(
+        params[0].parse::<u64>()?,
+        clipboard::ClipboardSetTarget::Primary,
+    )?;
+
+    Ok(())
+}
+
+pub fn set_both(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    clipboard::ClipdClient::new().set_by_id(
+        params[0].parse::<u64>()?,
+        clipboard::ClipboardSetTarget::Both,
+    )?;
+
+    Ok(())
+}
+
+fn list_search_hits(
+    rofi: &RofiPlugin,
+    hits: Vec<clipboard::ClipboardSearchHit>,
+) -> anyhow::Result<()> {
+    if hits.is_empty() {
+        return Err(RofiPluginError::new("no matching entry").into());
+    }
+
+    rofi.set_markup_rows();
+    for hit in hits {
+        rofi.add_menu_entry_with_params(&hit.snippet, set_clipboard, vec![hit.id.to_string()])
+    }
+    rofi.add_menu_entry("[back]", list_clipboard);
+
+    Ok(())
+}
+
+pub fn search_clipboard(rofi: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    list_search_hits(
+        rofi,
+        clipboard::ClipdClient::new().search(&params[0], false, true)?,
+    )
+}
+
+pub fn regex_search_clipboard(rofi: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    list_search_hits(
+        rofi,
+        clipboard::ClipdClient::new().search(&params[0], true, false)?,
+    )
+}
+
+pub fn manage_clipboard(rofi: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
+    let client = clipboard::ClipdClient::new();
+    let clipboard_datas = client.list()?;
+
+    // 旧版本的 clipd 没有 /stats, 拿不到的话不显示
+    if let Ok(stats) = client.stats() {
+        let format_bytes = |x: usize| {
+            byte_unit::Byte::from_bytes(x as u128)
+                .get_appropriate_unit(false)
+                .to_string()
+        };
+        rofi.set_message(&format!(
+            "{} entries, {} pinned, {} used ({} images)",
+            stats.entry_count,
+            stats.pinned_count,
+            format_bytes(stats.total_bytes),
+            format_bytes(stats.image_bytes)
+        ));
+    }
+
+    for entry in clipboard_datas.iter() {
+        rofi.add_menu_entry_with_options(
+            &format_entry(entry),
+            entry_menu,
+            vec![entry.id.to_string()],
+            entry_options(entry),
+        )
+    }
+    rofi.add_menu_entry("[delete_matching]", delete_matching);
+    rofi.add_menu_entry("[clear]", clear_clipboard);
+    let encryption_config = file::encryption_config();
+    if file::is_locked() {
+        match encryption_config.key_source {
+            file::EncryptionKeySource::Passphrase if file::has_passphrase() => {
+                rofi.add_menu_entry_with_params("[unlock]", unlock_with_passphrase, vec![])
+            }
+            file::EncryptionKeySource::Passphrase => {
+                rofi.add_menu_entry_with_params("[set_passphrase]", set_passphrase, vec![])
+            }
+            file::EncryptionKeySource::SecretService => {
+                rofi.add_menu_entry("[unlock]", unlock_with_secret_service)
+            }
+        }
+    } else if encryption_config.enabled {
+        rofi.add_menu_entry("[lock]", lock_history);
+    }
+    rofi.add_menu_entry("[back]", list_clipboard);
+
+    Ok(())
+}
+
+pub fn entry_menu(rofi: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    let id = params[0].parse::<u64>()?;
+    let clipboard_datas = clipboard::ClipdClient::new().list()?;
+    let entry = if let Some(entry) = clipboard_datas.iter().find(|e| e.id == id) {
+        entry
+    } else {
+        return Err(RofiPluginError::new("no such entry").into());
+    };
+
+    let last_used = chrono::DateTime::from_timestamp(entry.last_used, 0)
+        .map(|x| {
+            x.with_timezone(&chrono::Local)
+                .format("%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default();
+    rofi.set_message(&format!(
+        "<b>{}</b> {} chars, {} bytes, used {} times, last used {}{}\n{}",
+        entry.kind,
+        entry.char_count,
+        entry.byte_length,
+        entry.use_count,
+        last_used,
+        entry
+            .source
+            .as_ref()
+            .map(|x| format!(", from {}", html_escape::encode_safe(&x.class)))
+            .unwrap_or_default(),
+        html_escape::encode_safe(&entry.preview).replace("\n", " ")
+    ));
+    rofi.add_menu_entry_with_params("[set]", set_clipboard, vec![id.to_string()]);
+    rofi.add_menu_entry_with_params("[preview]", preview_clipboard, vec![id.to_string()]);
+    rofi.add_menu_entry_with_params("[set_primary]", set_primary, vec![id.to_string()]);
+    rofi.add_menu_entry_with_params("[set_both]", set_both, vec![id.to_string()]);
+    rofi.add_menu_entry_with_params("[delete]", delete_clipboard, vec![id.to_string()]);
+    if entry.pinned {
+        rofi.add_menu_entry_with_params("[unpin]", unpin_clipboard, vec![id.to_string()]);
+    } else {
+        rofi.add_menu_entry_with_params("[pin]", pin_clipboard, vec![id.to_string()]);
+    }
+    rofi.add_menu_entry("[back]", manage_clipboard);
+
+    Ok(())
+}
+
+// 预览的图片保留的时间, 之后的预览会删掉更早的
+const PREVIEW_KEEP: std::time::Duration = std::time::Duration::from_secs(60);
+
+// 只放在当前用户自己的目录里, 没有运行时目录的话用缓存目录
+fn preview_dir() -> anyhow::Result<std::path::PathBuf> {
+    let preview_dir = match dirs::runtime_dir() {
+        Some(x) => x.join("clipc-preview"),
+        None => file::get_cache_dir("clipc")?.join("preview"),
+    };
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&preview_dir)?;
+    Ok(preview_dir)
+}
+
+fn cleanup_previews(preview_dir: &std::path::Path) {
+    let entries = match std::fs::read_dir(preview_dir) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|x| x.modified())
+            .ok()
+            .and_then(|x| x.elapsed().ok())
+            .is_some_and(|x| x > PREVIEW_KEEP);
+        if expired {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+pub fn preview_clipboard(rofi: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    // 太长的话 rofi 的 message 会超出屏幕
+    const PREVIEW_LIMIT: usize = 4096;
+
+    let id = params[0].parse::<u64>()?;
+    let client = clipboard::ClipdClient::new();
+    let result = client.get_by_id(id)?;
+
+    if let Some(hash) = &result.image_hash {
+        // rofi 里显示不了大图, 交给默认的图片查看器
+        let image = client.get_image(hash)?;
+        let preview_dir = preview_dir()?;
+        cleanup_previews(&preview_dir);
+        let image_path = preview_dir.join(format!("{}.png", hash));
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&image_path)?
+            .write_all(&image)?;
+        // xdg-open 可能在查看器打开文件之前就返回了, 文件留到下次预览的时候再删
+        std::process::Command::new("xdg-open")
+            .arg(&image_path)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()?;
+        return Ok(());
+    }
+
+    let content = result.content.unwrap_or(result.item.preview);
+    let mut preview = content.chars().take(PREVIEW_LIMIT).collect::<String>();
+    if preview.len() < content.len() {
+        preview.push_str("...");
+    }
+    rofi.set_message(&html_escape::encode_safe(&preview));
+    rofi.add_menu_entry_with_params("[set]", set_clipboard, vec![id.to_string()]);
+    rofi.add_menu_entry_with_params("[back]", entry_menu, vec![id.to_string()]);
+
+    Ok(())
+}
+
+pub fn unlock_with_passphrase(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    file::unlock(Some(&params[0]))?;
+    clipboard::ClipdClient::new().unlock()?;
+    Ok(())
+}
+
+pub fn set_passphrase(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    file::set_passphrase(&params[0], &params[1])?;
+    clipboard::ClipdClient::new().unlock()?;
+    Ok(())
+}
+
+pub fn unlock_with_secret_service(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
+    file::unlock(None)?;
+    clipboard::ClipdClient::new().unlock()?;
+    Ok(())
+}
+
+pub fn lock_history(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
+    clipboard::ClipdClient::new().lock()?;
+    file::lock()?;
+    Ok(())
+}
+
+pub fn delete_clipboard(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    clipboard::ClipdClient::new().delete_by_id(params[0].parse::<u64>()?)?;
+
+    Ok(())
+}
+
+pub fn delete_matching(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    clipboard::ClipdClient::new().delete_matching(&params[0], true)?;
+
+    Ok(())
+}
+
+pub fn clear_clipboard(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
+    clipboard::ClipdClient::new().clear(true)?;
+
+    Ok(())
+}
+
+pub fn pin_clipboard(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    clipboard::ClipdClient::new().pin_by_id(params[0].parse::<u64>()?)?;
+
+    Ok(())
+}
+
+pub fn unpin_clipboard(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    clipboard::ClipdClient::new().unpin_by_id(params[0].parse::<u64>()?)?;
+
+    Ok(())
+}
+
+// 不是 rofi 调用的时候作为命令行工具使用, 文件是 - 的时候读写 stdin/stdout
+fn run_cli(args: &[String]) -> anyhow::Result<()> {
+    let client = clipboard::ClipdClient::new();
+    match args {
+        [command, path] if command == "export" => {
+            let archive = client.export()?;
+            if path == "-" {
+                std::io::stdout().write_all(&archive)?;
+            } else {
+                std::fs::write(path, archive)?;
+            }
+        }
+        [command, path] if command == "import" => {
+            let archive = if path == "-" {
+                let mut archive = Vec::new();
+                std::io::stdin().read_to_end(&mut archive)?;
+                archive
+            } else {
+                std::fs::read(path)?
+            };
+            let imported = client.import(&archive)?;
+            println!("imported {} entries", imported);
+        }
+        _ => return Err(RofiPluginError::new("usage: clipc export|import <file|->").into()),
+    }
 
     Ok(())
 }
 
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if std::env::var_os("ROFI_RETV").is_none() && !args.is_empty() {
+        if let Err(err) = run_cli(&args) {
+            eprintln!("{:?}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut rofi = RofiPlugin::new();
 
     rofi.register_entrypoint(list_clipboard);
 
-    rofi.register_callback_with_params(set_clipboard, vec![String::from("idx")]);
+    rofi.register_callback_with_params(set_clipboard, vec![String::from("id")]);
+    rofi.register_callback_with_params(set_primary, vec![String::from("id")]);
+    rofi.register_callback_with_params(set_both, vec![String::from("id")]);
+    rofi.register_callback_with_params(search_clipboard, vec![String::from("query")]);
+    rofi.register_callback_with_params(regex_search_clipboard, vec![String::from("regex")]);
+    rofi.register_callback(manage_clipboard);
+    rofi.register_callback(list_apps);
+    rofi.register_callback_with_params(list_app_entries, vec![String::from("app")]);
+    rofi.register_callback_with_params(entry_menu, vec![String::from("id")]);
+    rofi.register_callback_with_params(preview_clipboard, vec![String::from("id")]);
+    rofi.register_callback_with_params(delete_clipboard, vec![String::from("id")]);
+    rofi.register_callback_with_params(delete_matching, vec![String::from("regex")]);
+    rofi.register_callback(clear_clipboard);
+    rofi.register_callback_with_params(unlock_with_passphrase, vec![String::from("passphrase")]);
+    rofi.register_callback_with_params(
+        set_passphrase,
+        vec![
+            String::from("passphrase"),
+            String::from("confirm passphrase"),
+        ],
+    );
+    rofi.register_callback(unlock_with_secret_service);
+    rofi.register_callback(lock_history);
+    rofi.register_callback_with_params(pin_clipboard, vec![String::from("id")]);
+    rofi.register_callback_with_params(unpin_clipboard, vec![String::from("id")]);
 
     rofi.run();
 }
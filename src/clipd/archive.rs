@@ -0,0 +1,129 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+
+use crate::history::HistoryRecord;
+use crate::manager::ClipboardData;
+use crate::stroage::ClipboardEntry;
+use rofi_toys::rofi::RofiPluginError;
+
+const MANIFEST_PATH: &str = "manifest.json";
+const IMAGES_DIR: &str = "images";
+// 归档格式有不兼容的修改时加一
+const ARCHIVE_VERSION: u32 = 1;
+
+// 图片只能是 images/{hash}.png, hash 之后会被拼进文件路径
+static IMAGE_PATH_REGEX: Lazy<regex::Regex> =
+    Lazy::new(|| regex::Regex::new(&format!(r"^{}/([0-9a-f]+)\.png$", IMAGES_DIR)).unwrap());
+
+#[derive(Serialize, Deserialize)]
+struct ArchiveManifest {
+    version: u32,
+    exported_at: i64,
+    // 和历史记录的格式相同, 图片单独存成 images/{hash}.png
+    entries: Vec<HistoryRecord>,
+}
+
+fn append_file(builder: &mut tar::Builder<Vec<u8>>, path: &str, data: &[u8]) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(chrono::Local::now().timestamp() as u64);
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+// 打包成 tar, manifest.json 放在最前面
+pub fn export_entries<'a>(
+    entries: impl Iterator<Item = &'a ClipboardEntry>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut records = Vec::new();
+    let mut images = Vec::new();
+    let mut image_hashes = HashSet::new();
+
+    for entry in entries {
+        if let ClipboardData::Image(pixbuf, hash, _) = &entry.data {
+            if image_hashes.insert(hash.clone()) {
+                images.push((hash.clone(), pixbuf.save_to_bufferv("png", &[])?));
+            }
+        }
+        records.push(HistoryRecord::from_entry(entry));
+    }
+
+    let manifest = ArchiveManifest {
+        version: ARCHIVE_VERSION,
+        exported_at: chrono::Local::now().timestamp(),
+        entries: records,
+    };
+
+    let mut builder = tar::Builder::new(Vec::new());
+    append_file(
+        &mut builder,
+        MANIFEST_PATH,
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    for (hash, png) in images {
+        append_file(&mut builder, &format!("{}/{}.png", IMAGES_DIR, hash), &png)?;
+    }
+
+    return Ok(builder.into_inner()?);
+}
+
+pub fn import_entries(data: &[u8]) -> anyhow::Result<Vec<ClipboardEntry>> {
+    let mut archive = tar::Archive::new(data);
+    let mut manifest = None;
+    let mut images = HashMap::new();
+
+    for file in archive.entries()? {
+        let mut file = file?;
+        let path = file.path()?.to_string_lossy().to_string();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        if path == MANIFEST_PATH {
+            manifest = Some(serde_json::from_slice::<ArchiveManifest>(&content)?);
+        } else if let Some(caps) = IMAGE_PATH_REGEX.captures(&path) {
+            images.insert(caps[1].to_string(), content);
+        } else {
+            return Err(
+                RofiPluginError::new(&format!("unexpected file in archive: {}", path)).into(),
+            );
+        }
+    }
+
+    let manifest = match manifest {
+        Some(x) => x,
+        None => return Err(RofiPluginError::new("no manifest.json in archive").into()),
+    };
+    if manifest.version > ARCHIVE_VERSION {
+        return Err(RofiPluginError::new(&format!(
+            "unsupported archive version: {}",
+            manifest.version
+        ))
+        .into());
+    }
+
+    let entries = manifest
+        .entries
+        .into_iter()
+        .filter_map(|record| {
+            let mut entry = record.into_entry(|hash| {
+                match ClipboardData::from_image_bytes(images.get(hash)?) {
+                    Ok(ClipboardData::Image(pixbuf, _, _)) => Some(pixbuf),
+                    _ => {
+                        log::error!("failed to load image {} from archive", hash);
+                        None
+                    }
+                }
+            })?;
+            // 归档里的 hash 不可信, 按解码出来的图片重新计算
+            if let ClipboardData::Image(pixbuf, hash, _) = &mut entry.data {
+                *hash = ClipboardData::calc_image_hash(pixbuf);
+            }
+            Some(entry)
+        })
+        .collect();
+
+    return Ok(entries);
+}
@@ -0,0 +1,162 @@
+use gtk::prelude::*;
+use rofi_toys::clipboard;
+use std::rc::Rc;
+
+use crate::manager::{ClipboardData, ClipboardFormat};
+use crate::{gtk_backend, wlr_backend};
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipdBackendKind {
+    // wayland 下优先用 wlr-data-control, 不支持的话退回 gtk
+    #[default]
+    Auto,
+    Gtk,
+    Wlr,
+}
+
+// 按优先级排列的纯文本 target
+const TEXT_TARGETS: [&str; 5] = [
+    "text/plain;charset=utf-8",
+    "UTF8_STRING",
+    "text/plain",
+    "STRING",
+    "TEXT",
+];
+
+// 复制来源自己提供了纯文本的话, 恢复的时候用它, 不再从 html 之类的数据转换
+pub fn needs_text_targets(data: &ClipboardData, formats: &[ClipboardFormat]) -> bool {
+    if data.to_searchable_text().is_none() {
+        return false;
+    }
+    if let ClipboardData::Text(_) = data {
+        return true;
+    }
+    return !formats.iter().any(|format| {
+        format.target.starts_with("text/plain")
+            || TEXT_TARGETS
+                .iter()
+                .any(|x| format.target.eq_ignore_ascii_case(x))
+    });
+}
+
+// 一个 selection (CLIPBOARD 或 PRIMARY) 的读写接口
+// 所有方法都在主线程调用
+pub trait ClipboardBackend {
+    // owner 变化的时候调用 callback, 包括 owner 消失
+    fn connect_owner_change(&self, callback: Box<dyn Fn()>);
+
+    // None 代表当前没有 owner
+    fn targets(&self) -> Option<Vec<String>>;
+
+    fn read(&self, target: &str) -> Option<Vec<u8>>;
+
+    // 不阻塞主循环的 read, 读完或者失败的时候调用一次 callback
+    fn read_async(&self, target: &str, callback: Box<dyn FnOnce(Option<Vec<u8>>)>);
+
+    // 成为 owner, 提供 data 能转换出的 target, formats 只补充 data 提供不了的 target
+    fn offer(&self, data: ClipboardData, formats: Vec<ClipboardFormat>);
+
+    fn read_text(&self) -> Option<String> {
+        let targets = self.targets()?;
+        let target = TEXT_TARGETS
+            .iter()
+            .find(|x| targets.iter().any(|t| t.eq_ignore_ascii_case(x)))
+            .map(|x| x.to_string())
+            .or_else(|| targets.into_iter().find(|x| x.starts_with("text/")))?;
+
+        self.read(&target)
+            .map(|x| String::from_utf8_lossy(&x).to_string())
+    }
+
+    fn read_uris(&self) -> Vec<String> {
+        match self.read("text/uri-list") {
+            Some(data) => String::from_utf8_lossy(&data)
+                .lines()
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty() && !x.starts_with('#'))
+                .map(|x| x.to_string())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn read_image(&self) -> Option<gtk::gdk_pixbuf::Pixbuf> {
+        let targets = self.targets()?;
+        let target = if targets.iter().any(|x| x == "image/png") {
+            "image/png".to_string()
+        } else {
+            targets.into_iter().find(|x| x.starts_with("image/"))?
+        };
+
+        let data = self.read(&target)?;
+        let loader = gtk::gdk_pixbuf::PixbufLoader::new();
+        if let Err(err) = loader.write(&data).and_then(|_| loader.close()) {
+            log::error!("failed to decode {}: {}", target, err);
+            return None;
+        }
+        return loader.pixbuf();
+    }
+}
+
+// 返回 (CLIPBOARD, PRIMARY) 两个 backend
+pub fn create_backends(
+    kind: ClipdBackendKind,
+) -> (Rc<dyn ClipboardBackend>, Rc<dyn ClipboardBackend>) {
+    let use_wlr = match kind {
+        ClipdBackendKind::Auto => std::env::var_os("WAYLAND_DISPLAY").is_some(),
+        ClipdBackendKind::Gtk => false,
+        ClipdBackendKind::Wlr => true,
+    };
+
+    if use_wlr {
+        match wlr_backend::WlrBackend::new() {
+            Ok((clipboard, primary)) => {
+                log::info!("using wlr-data-control clipboard backend");
+                return (Rc::new(clipboard), Rc::new(primary));
+            }
+            Err(err) => {
+                log::error!("failed to init wlr backend, fallback to gtk: {:?}", err);
+            }
+        }
+    }
+
+    log::info!("using gtk clipboard backend");
+    return (
+        Rc::new(gtk_backend::GtkBackend::new(
+            clipboard::ClipboardSelection::Clipboard,
+        )),
+        Rc::new(gtk_backend::GtkBackend::new(
+            clipboard::ClipboardSelection::Primary,
+        )),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(target: &str) -> ClipboardFormat {
+        return ClipboardFormat {
+            target: target.to_string(),
+            data: b"plain".to_vec(),
+        };
+    }
+
+    #[test]
+    fn text_targets_only_when_source_offered_none() {
+        let html = ClipboardData::Html("<b>plain</b>".to_string());
+        assert!(needs_text_targets(&html, &[format("text/rtf")]));
+        assert!(!needs_text_targets(
+            &html,
+            &[format("text/plain;charset=utf-8")]
+        ));
+        assert!(!needs_text_targets(&html, &[format("utf8_string")]));
+
+        let text = ClipboardData::Text("plain".to_string());
+        assert!(needs_text_targets(&text, &[format("text/plain")]));
+
+        let binary = ClipboardData::Binary("application/pdf".to_string(), Vec::new());
+        assert!(!needs_text_targets(&binary, &[]));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::manager::ClipboardData;
+use rofi_toys::clipboard;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ClipdFilterConfig {
+    // 带有这些 target 的剪贴板内容视为敏感内容, 支持末尾 * 通配
+    pub ignore_targets: Vec<String>,
+    // 内容匹配这些正则的视为敏感内容
+    pub ignore_patterns: Vec<String>,
+    // 0 代表敏感内容直接不记录, 否则记录并在 N 秒后删除
+    pub sensitive_expire_secs: u32,
+    // 来自这些应用的内容不记录, 按 WM_CLASS 匹配, 不区分大小写
+    pub ignore_apps: Vec<String>,
+}
+
+impl Default for ClipdFilterConfig {
+    fn default() -> Self {
+        ClipdFilterConfig {
+            ignore_targets: vec![
+                "x-kde-passwordManagerHint".to_string(),
+                "application/x-keepassxc-*".to_string(),
+            ],
+            ignore_patterns: Vec::new(),
+            sensitive_expire_secs: 0,
+            ignore_apps: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SensitiveFilter {
+    ignore_targets: Vec<String>,
+    ignore_apps: Vec<String>,
+    ignore_patterns: Vec<regex::Regex>,
+    pub expire_secs: u32,
+}
+
+impl SensitiveFilter {
+    pub fn new(config: &ClipdFilterConfig) -> SensitiveFilter {
+        let ignore_patterns = config
+            .ignore_patterns
+            .iter()
+            .filter_map(|pattern| match regex::Regex::new(pattern) {
+                Ok(x) => Some(x),
+                Err(err) => {
+                    log::error!("invalid ignore pattern {}: {}", pattern, err);
+                    None
+                }
+            })
+            .collect();
+
+        return SensitiveFilter {
+            ignore_targets: config
+                .ignore_targets
+                .iter()
+                .map(|x| x.to_lowercase())
+                .collect(),
+            ignore_apps: config
+                .ignore_apps
+                .iter()
+                .map(|x| x.to_lowercase())
+                .collect(),
+            ignore_patterns,
+            expire_secs: config.sensitive_expire_secs,
+        };
+    }
+
+    pub fn is_sensitive_targets(&self, targets: &[String]) -> bool {
+        targets.iter().any(|target| {
+            let target = target.to_lowercase();
+            self.ignore_targets.iter().any(|x| {
+                if let Some(prefix) = x.strip_suffix('*') {
+                    target.starts_with(prefix)
+                } else {
+                    target == *x
+                }
+            })
+        })
+    }
+
+    pub fn is_ignored_app(&self, source: &clipboard::ClipboardSource) -> bool {
+        let class = source.class.to_lowercase();
+        let instance = source.instance.to_lowercase();
+        self.ignore_apps
+            .iter()
+            .any(|x| *x == class || *x == instance)
+    }
+
+    pub fn is_sensitive_data(&self, data: &ClipboardData) -> bool {
+        if let Some(text) = data.to_searchable_text() {
+            self.ignore_patterns.iter().any(|x| x.is_match(&text))
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(class: &str, instance: &str) -> clipboard::ClipboardSource {
+        return clipboard::ClipboardSource {
+            class: class.to_string(),
+            instance: instance.to_string(),
+            title: String::new(),
+        };
+    }
+
+    #[test]
+    fn sensitive_targets() {
+        let filter = SensitiveFilter::new(&ClipdFilterConfig::default());
+        assert!(filter.is_sensitive_targets(&["x-kde-passwordmanagerhint".to_string()]));
+        assert!(filter.is_sensitive_targets(&[
+            "UTF8_STRING".to_string(),
+            "application/x-keepassxc-hint".to_string(),
+        ]));
+        assert!(!filter.is_sensitive_targets(&["UTF8_STRING".to_string()]));
+        assert!(!filter.is_sensitive_targets(&[]));
+    }
+
+    #[test]
+    fn ignored_apps() {
+        let filter = SensitiveFilter::new(&ClipdFilterConfig {
+            ignore_apps: vec!["KeePassXC".to_string()],
+            ..Default::default()
+        });
+        assert!(filter.is_ignored_app(&source("KeePassXC", "keepassxc")));
+        assert!(filter.is_ignored_app(&source("Other", "keepassxc")));
+        assert!(!filter.is_ignored_app(&source("firefox", "Navigator")));
+    }
+
+    #[test]
+    fn sensitive_data() {
+        let filter = SensitiveFilter::new(&ClipdFilterConfig {
+            // 无效的正则被忽略
+            ignore_patterns: vec![r"^\d{6}$".to_string(), "(".to_string()],
+            ..Default::default()
+        });
+        assert!(filter.is_sensitive_data(&ClipboardData::Text("123456".to_string())));
+        assert!(!filter.is_sensitive_data(&ClipboardData::Text("1234567".to_string())));
+        assert!(!filter.is_sensitive_data(&ClipboardData::Binary(
+            "application/octet-stream".to_string(),
+            b"123456".to_vec(),
+        )));
+    }
+}
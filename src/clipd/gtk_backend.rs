@@ -0,0 +1,205 @@
+use gtk::prelude::*;
+use rofi_toys::clipboard;
+
+use crate::backend::{self, ClipboardBackend};
+use crate::manager::{ClipboardData, ClipboardDataType, ClipboardFormat};
+
+// ClipboardFormat 的 info 从这里开始, 避免和 ClipboardDataType 冲突
+const FORMAT_INFO_OFFSET: u32 = 1000;
+
+// 基于 gtk::Clipboard, 只能在 X11/XWayland 下工作
+pub struct GtkBackend {
+    clipboard: gtk::Clipboard,
+}
+
+impl GtkBackend {
+    pub fn new(selection: clipboard::ClipboardSelection) -> GtkBackend {
+        let atom = match selection {
+            clipboard::ClipboardSelection::Clipboard => gtk::gdk::SELECTION_CLIPBOARD,
+            clipboard::ClipboardSelection::Primary => gtk::gdk::SELECTION_PRIMARY,
+        };
+
+        return GtkBackend {
+            clipboard: gtk::Clipboard::get(&atom),
+        };
+    }
+}
+
+// 和 gtk::Clipboard::set_text 提供的相同
+const TEXT_TARGETS: [&str; 6] = [
+    "UTF8_STRING",
+    "COMPOUND_TEXT",
+    "TEXT",
+    "STRING",
+    "text/plain;charset=utf-8",
+    "text/plain",
+];
+
+// data 自己能提供的 target, 由 fill_selection 负责转换
+fn default_targets(
+    data: &ClipboardData,
+    formats: &[ClipboardFormat],
+) -> Vec<(String, ClipboardDataType)> {
+    let mut targets = Vec::new();
+    match data {
+        ClipboardData::Url(_) => {
+            targets.push(("text/uri-list".to_string(), ClipboardDataType::Url))
+        }
+        ClipboardData::Html(_) => targets.push(("text/html".to_string(), ClipboardDataType::Html)),
+        ClipboardData::Image(..) => {
+            // 和 gtk::Clipboard::set_image 一样, 提供 gdk-pixbuf 能写的所有格式
+            targets.push(("image/png".to_string(), ClipboardDataType::Image));
+            for format in gtk::gdk_pixbuf::Pixbuf::formats() {
+                if !format.is_writable() {
+                    continue;
+                }
+                for mime in format.mime_types() {
+                    if !targets.iter().any(|(x, _)| *x == mime) {
+                        targets.push((mime.to_string(), ClipboardDataType::Image));
+                    }
+                }
+            }
+        }
+        // 二进制数据的 mime 不固定, 在 offer 里当作 ClipboardFormat 提供
+        ClipboardData::Text(_) | ClipboardData::Binary(..) => {}
+    }
+    if backend::needs_text_targets(data, formats) {
+        for target in TEXT_TARGETS {
+            targets.push((target.to_string(), ClipboardDataType::Text));
+        }
+    }
+    return targets;
+}
+
+fn fill_selection(
+    data: &ClipboardData,
+    selection: &gtk::SelectionData,
+    data_type: ClipboardDataType,
+) {
+    match (data, data_type) {
+        (ClipboardData::Url(urls), ClipboardDataType::Url) => {
+            selection.set_uris(
+                urls.iter()
+                    .map(|u| u.as_str())
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+            );
+        }
+        (ClipboardData::Html(html), ClipboardDataType::Html) => {
+            selection.set(&gtk::gdk::Atom::intern("text/html"), 8, html.as_bytes());
+        }
+        (ClipboardData::Image(image, _, _), ClipboardDataType::Image) => {
+            selection.set_pixbuf(image);
+        }
+        (_, ClipboardDataType::Text) => {
+            selection.set_text(&data.to_searchable_text().unwrap_or_default());
+        }
+        _ => {}
+    }
+}
+
+impl ClipboardBackend for GtkBackend {
+    fn connect_owner_change(&self, callback: Box<dyn Fn()>) {
+        self.clipboard
+            .connect_local("owner-change", false, move |_| {
+                callback();
+                return None;
+            });
+    }
+
+    fn targets(&self) -> Option<Vec<String>> {
+        self.clipboard
+            .wait_for_targets()
+            .map(|atoms| atoms.iter().map(|x| x.name().to_string()).collect())
+    }
+
+    fn read(&self, target: &str) -> Option<Vec<u8>> {
+        self.clipboard
+            .wait_for_contents(&gtk::gdk::Atom::intern(target))
+            .map(|x| x.data())
+    }
+
+    fn read_async(&self, target: &str, callback: Box<dyn FnOnce(Option<Vec<u8>>)>) {
+        self.clipboard
+            .request_contents(&gtk::gdk::Atom::intern(target), move |_, selection| {
+                // 读取失败的时候 length 是负数
+                if selection.length() < 0 {
+                    callback(None);
+                } else {
+                    callback(Some(selection.data()));
+                }
+            });
+    }
+
+    fn offer(&self, data: ClipboardData, mut formats: Vec<ClipboardFormat>) {
+        if let ClipboardData::Binary(mime, bytes) = &data {
+            if !formats.iter().any(|x| x.target == *mime) {
+                formats.insert(
+                    0,
+                    ClipboardFormat {
+                        target: mime.clone(),
+                        data: bytes.clone(),
+                    },
+                );
+            }
+        }
+
+        // data 自己能提供的 target 在前面, 原样保存的 formats 只补充缺少的
+        let defaults = default_targets(&data, &formats);
+        formats.retain(|x| !defaults.iter().any(|(target, _)| *target == x.target));
+
+        match (&data, formats.is_empty()) {
+            (ClipboardData::Text(text), true) => {
+                self.clipboard.set_text(text);
+                return;
+            }
+            (ClipboardData::Image(image, _, _), true) => {
+                self.clipboard.set_image(image);
+                return;
+            }
+            _ => {}
+        }
+
+        let mut targets = defaults
+            .iter()
+            .map(|(target, data_type)| {
+                gtk::TargetEntry::new(target, gtk::TargetFlags::OTHER_APP, *data_type as u32)
+            })
+            .collect::<Vec<_>>();
+        for (idx, format) in formats.iter().enumerate() {
+            targets.push(gtk::TargetEntry::new(
+                &format.target,
+                gtk::TargetFlags::OTHER_APP,
+                FORMAT_INFO_OFFSET + idx as u32,
+            ));
+        }
+
+        self.clipboard
+            .set_with_data(targets.as_slice(), move |_, selection, info| {
+                if info >= FORMAT_INFO_OFFSET {
+                    if let Some(format) = formats.get((info - FORMAT_INFO_OFFSET) as usize) {
+                        selection.set(&gtk::gdk::Atom::intern(&format.target), 8, &format.data);
+                    }
+                } else {
+                    fill_selection(&data, selection, ClipboardDataType::from_u32(info));
+                }
+            });
+    }
+
+    // gtk 自己会处理各种编码转换, 比直接读 target 更可靠
+    fn read_text(&self) -> Option<String> {
+        self.clipboard.wait_for_text().map(|x| x.to_string())
+    }
+
+    fn read_uris(&self) -> Vec<String> {
+        self.clipboard
+            .wait_for_uris()
+            .iter()
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    fn read_image(&self) -> Option<gtk::gdk_pixbuf::Pixbuf> {
+        self.clipboard.wait_for_image()
+    }
+}
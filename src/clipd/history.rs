@@ -0,0 +1,357 @@
+use base64::engine::general_purpose as base64_engine;
+use base64::Engine;
+use chrono::TimeZone;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::manager::{ClipboardData, ClipboardFormat};
+use crate::stroage::ClipboardEntry;
+use rofi_toys::rofi::RofiPluginError;
+use rofi_toys::{clipboard, file};
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum HistoryData {
+    Text { text: String },
+    Url { urls: Vec<String> },
+    Html { html: String },
+    // 图片只记录 hash, 内容单独存成 images/{hash}.png
+    Image { hash: String, time: i64 },
+    // data 是 base64
+    Binary { mime: String, data: String },
+}
+
+#[derive(Serialize, Deserialize)]
+struct HistoryFormat {
+    target: String,
+    // base64
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HistoryRecord {
+    #[serde(default)]
+    id: u64,
+    // 旧的图片记录没有 first_seen, 只有和图片数据在一起的 time, 读取的时候补上
+    #[serde(default)]
+    first_seen: Option<i64>,
+    #[serde(default)]
+    last_used: Option<i64>,
+    #[serde(default = "default_use_count")]
+    use_count: u32,
+    #[serde(flatten)]
+    data: HistoryData,
+    #[serde(default)]
+    selection: clipboard::ClipboardSelection,
+    #[serde(default)]
+    targets: Vec<String>,
+    #[serde(default)]
+    formats: Vec<HistoryFormat>,
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default)]
+    source: Option<clipboard::ClipboardSource>,
+}
+
+// 历史记录和导出的归档共用的转换, 图片由调用方负责读写
+impl HistoryRecord {
+    pub fn from_entry(entry: &ClipboardEntry) -> HistoryRecord {
+        let data = match &entry.data {
+            ClipboardData::Text(text) => HistoryData::Text { text: text.clone() },
+            ClipboardData::Url(urls) => HistoryData::Url { urls: urls.clone() },
+            ClipboardData::Html(html) => HistoryData::Html { html: html.clone() },
+            ClipboardData::Image(_, hash, time) => HistoryData::Image {
+                hash: hash.clone(),
+                time: time.timestamp(),
+            },
+            ClipboardData::Binary(mime, data) => HistoryData::Binary {
+                mime: mime.clone(),
+                data: base64_engine::STANDARD.encode(data),
+            },
+        };
+
+        return HistoryRecord {
+            id: entry.id,
+            first_seen: Some(entry.first_seen.timestamp()),
+            last_used: Some(entry.last_used.timestamp()),
+            use_count: entry.use_count,
+            data,
+            selection: entry.selection,
+            targets: entry.targets.clone(),
+            formats: entry
+                .formats
+                .iter()
+                .map(|format| HistoryFormat {
+                    target: format.target.clone(),
+                    data: base64_engine::STANDARD.encode(&format.data),
+                })
+                .collect(),
+            pinned: entry.pinned,
+            source: entry.source.clone(),
+        };
+    }
+
+    // 图片找不到或者数据损坏的时候返回 None
+    pub fn into_entry(
+        self,
+        load_image: impl Fn(&str) -> Option<gtk::gdk_pixbuf::Pixbuf>,
+    ) -> Option<ClipboardEntry> {
+        let data = match self.data {
+            HistoryData::Text { text } => ClipboardData::Text(text),
+            HistoryData::Url { urls } => ClipboardData::Url(urls),
+            HistoryData::Html { html } => ClipboardData::Html(html),
+            HistoryData::Image { hash, time } => {
+                let pixbuf = load_image(&hash)?;
+                let time = timestamp_to_local(time).unwrap_or_else(chrono::Local::now);
+                ClipboardData::Image(pixbuf, hash, time)
+            }
+            HistoryData::Binary { mime, data } => {
+                ClipboardData::Binary(mime, base64_engine::STANDARD.decode(data).ok()?)
+            }
+        };
+
+        let first_seen = self.first_seen.or(match &data {
+            ClipboardData::Image(_, _, time) => Some(time.timestamp()),
+            _ => None,
+        });
+
+        let mut entry = ClipboardEntry::new(data);
+        entry.id = self.id;
+        if let Some(first_seen) = first_seen.and_then(timestamp_to_local) {
+            entry.first_seen = first_seen;
+            entry.last_used = first_seen;
+        }
+        if let Some(last_used) = self.last_used.and_then(timestamp_to_local) {
+            entry.last_used = last_used;
+        }
+        entry.use_count = self.use_count;
+        entry.selection = self.selection;
+        entry.targets = self.targets;
+        entry.formats = self
+            .formats
+            .into_iter()
+            .filter_map(|format| {
+                Some(ClipboardFormat {
+                    target: format.target,
+                    data: base64_engine::STANDARD.decode(format.data).ok()?,
+                })
+            })
+            .collect();
+        entry.pinned = self.pinned;
+        entry.source = self.source;
+        return Some(entry);
+    }
+}
+
+// history.json 的内容, next_id 也保存下来, 清空之后重启 id 也不会从头开始
+#[derive(Serialize, Deserialize)]
+struct HistoryFile {
+    next_id: u64,
+    entries: Vec<HistoryRecord>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HistoryFileCompat {
+    Current(HistoryFile),
+    // 旧版本只保存了记录的数组
+    Legacy(Vec<HistoryRecord>),
+}
+
+fn default_use_count() -> u32 {
+    1
+}
+
+fn timestamp_to_local(timestamp: i64) -> Option<chrono::DateTime<chrono::Local>> {
+    chrono::Local.timestamp_opt(timestamp, 0).single()
+}
+
+#[derive(Debug)]
+pub struct ClipboardHistory {
+    history_path: PathBuf,
+    images_dir: PathBuf,
+    // 开启加密时历史记录和图片都加密保存
+    encrypted: bool,
+    key: Option<file::EncryptionKey>,
+}
+
+impl ClipboardHistory {
+    pub fn new() -> anyhow::Result<ClipboardHistory> {
+        let data_dir = file::get_data_dir("clipd")?;
+        let images_dir = data_dir.join("images");
+        fs::create_dir_all(&images_dir)?;
+
+        return Ok(ClipboardHistory {
+            history_path: data_dir.join("history.json"),
+            images_dir,
+            encrypted: file::encryption_config().enabled,
+            // 没开启加密也带上 key, 用来读取之前加密过的数据
+            key: file::cached_key(),
+        });
+    }
+
+    fn image_path(&self, hash: &str) -> PathBuf {
+        self.images_dir.join(format!("{}.png", hash))
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    // 关掉加密之后磁盘上的历史记录可能还是加密的, 没有 key 的时候同样算作锁定,
+    // 否则第一次保存就会覆盖掉它, 图片也会被当成不在历史记录里删掉
+    pub fn is_locked(&self) -> bool {
+        self.key.is_none() && (self.encrypted || file::is_encrypted_file(&self.history_path))
+    }
+
+    pub fn set_key(&mut self, key: Option<file::EncryptionKey>) {
+        self.key = key;
+    }
+
+    // 加密和没加密的文件都能读
+    fn read_file(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let data = fs::read(path)?;
+        if !file::is_encrypted(&data) {
+            return Ok(data);
+        }
+        match &self.key {
+            Some(key) => key.decrypt(&data),
+            None => Err(RofiPluginError::new("clipboard history is locked").into()),
+        }
+    }
+
+    fn write_file(&self, path: &Path, data: &[u8]) -> anyhow::Result<()> {
+        match (&self.key, self.encrypted) {
+            (Some(key), true) => fs::write(path, key.encrypt(data)?)?,
+            (None, true) => return Err(RofiPluginError::new("clipboard history is locked").into()),
+            (_, false) => fs::write(path, data)?,
+        }
+        Ok(())
+    }
+
+    // 返回 (记录, 下一个可用的 id), 旧版本的历史记录里没有 id 的时候是 0
+    pub fn load(&self) -> anyhow::Result<(VecDeque<ClipboardEntry>, u64)> {
+        if !self.history_path.exists() {
+            return Ok((VecDeque::new(), 0));
+        }
+        let history_data = self.read_file(&self.history_path)?;
+        let (records, next_id) = match serde_json::from_slice(&history_data)? {
+            HistoryFileCompat::Current(x) => (x.entries, x.next_id),
+            HistoryFileCompat::Legacy(x) => (x, 0),
+        };
+
+        let entries = records
+            .into_iter()
+            .filter_map(|record| {
+                record.into_entry(|hash| {
+                    // 图片文件丢失的话直接丢掉这条记录
+                    let image_data = self.read_file(&self.image_path(hash));
+                    match image_data.and_then(|x| ClipboardData::from_image_bytes(&x)) {
+                        Ok(ClipboardData::Image(pixbuf, _, _)) => Some(pixbuf),
+                        Ok(_) => None,
+                        Err(err) => {
+                            log::error!("failed to load image {}: {}", hash, err);
+                            None
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        return Ok((entries, next_id));
+    }
+
+    pub fn save(&self, entries: &VecDeque<ClipboardEntry>, next_id: u64) -> anyhow::Result<()> {
+        // 还没解锁, 不能覆盖磁盘上加密的历史记录
+        if self.is_locked() {
+            return Ok(());
+        }
+
+        let mut image_hashes = HashSet::new();
+        let mut records = Vec::new();
+
+        // 敏感内容只保存在内存里
+        for entry in entries.iter().filter(|e| e.expires_at.is_none()) {
+            if let ClipboardData::Image(pixbuf, hash, _) = &entry.data {
+                // 开关加密之后已有的图片也要重新写一遍
+                let image_path = self.image_path(hash);
+                if !image_path.exists() || file::is_encrypted_file(&image_path) != self.encrypted {
+                    self.write_file(&image_path, &pixbuf.save_to_bufferv("png", &[])?)?;
+                }
+                image_hashes.insert(format!("{}.png", hash));
+            }
+            records.push(HistoryRecord::from_entry(entry));
+        }
+
+        // 先写临时文件再 rename, 避免写一半的时候挂掉把历史记录弄坏
+        let tmp_path = self.history_path.with_extension("json.tmp");
+        let history_file = HistoryFile {
+            next_id,
+            entries: records,
+        };
+        self.write_file(&tmp_path, &serde_json::to_vec(&history_file)?)?;
+        fs::rename(&tmp_path, &self.history_path)?;
+
+        // 清理已经不在历史记录里的图片, 保证磁盘上的数量也受 max_size 限制
+        for entry in fs::read_dir(&self.images_dir)? {
+            let entry = entry?;
+            if !image_hashes.contains(entry.file_name().to_string_lossy().as_ref()) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixbuf() -> gtk::gdk_pixbuf::Pixbuf {
+        return gtk::gdk_pixbuf::Pixbuf::new(gtk::gdk_pixbuf::Colorspace::Rgb, false, 8, 2, 2)
+            .unwrap();
+    }
+
+    #[test]
+    fn image_record_round_trip() {
+        let time = timestamp_to_local(1_700_000_000).unwrap();
+        let mut entry =
+            ClipboardEntry::new(ClipboardData::Image(pixbuf(), "abc".to_string(), time));
+        entry.id = 3;
+        entry.first_seen = timestamp_to_local(1_700_000_100).unwrap();
+        entry.last_used = timestamp_to_local(1_700_000_200).unwrap();
+
+        let json = serde_json::to_string(&HistoryRecord::from_entry(&entry)).unwrap();
+        let record: HistoryRecord = serde_json::from_str(&json).unwrap();
+        let loaded = record.into_entry(|hash| {
+            assert_eq!(hash, "abc");
+            Some(pixbuf())
+        });
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.id, 3);
+        assert_eq!(loaded.first_seen, entry.first_seen);
+        assert_eq!(loaded.last_used, entry.last_used);
+        match loaded.data {
+            ClipboardData::Image(_, hash, image_time) => {
+                assert_eq!(hash, "abc");
+                assert_eq!(image_time, time);
+            }
+            _ => panic!("expected an image"),
+        }
+    }
+
+    #[test]
+    fn legacy_image_record() {
+        let record: HistoryRecord =
+            serde_json::from_str(r#"{"type":"image","hash":"abc","time":1700000000}"#).unwrap();
+        let loaded = record.into_entry(|_| Some(pixbuf())).unwrap();
+
+        assert_eq!(loaded.first_seen.timestamp(), 1_700_000_000);
+        assert_eq!(loaded.last_used.timestamp(), 1_700_000_000);
+        assert!(matches!(loaded.data, ClipboardData::Image(_, ref hash, _) if hash == "abc"));
+    }
+}
@@ -1,34 +1,166 @@
-use crate::{manager, stroage};
-use actix_web::{middleware, rt, web, App, HttpServer, Responder};
-use rofi_toys::clipboard;
+use crate::backend::ClipboardBackend;
+use crate::{archive, manager, stroage};
+use actix_web::dev::Service;
+use actix_web::{middleware, rt, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use futures_util::future::{Either, FutureExt};
+use gtk::gio::prelude::ApplicationExt;
+use rofi_toys::rofi::RofiPluginError;
+use rofi_toys::{clipboard, file};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
+// /set, /import 之类直接传原始数据的接口, 图片可能比较大
+const MAX_PAYLOAD_SIZE: usize = 64 * 1024 * 1024;
+// 收到 /shutdown 之后等响应发出去再退出
+const SHUTDOWN_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
+// 连接建立时通过 SO_PEERCRED 拿到的对端信息
+#[derive(Clone, Copy)]
+struct PeerCredentials {
+    uid: u32,
+    pid: i32,
+}
+
+// socket 以 0600 创建, 如果已经存在且属于其他用户或者有 clipd 在运行则拒绝启动
+fn bind_socket(listen_path: &str) -> anyhow::Result<UnixListener> {
+    let uid = rustix::process::getuid().as_raw();
+    match std::fs::symlink_metadata(listen_path) {
+        Ok(md) => {
+            // 退回到 /tmp 的时候可能被其他用户抢先创建
+            if md.uid() != uid {
+                return Err(RofiPluginError::new(&format!(
+                    "{} is owned by uid {}, refusing to start",
+                    listen_path,
+                    md.uid()
+                ))
+                .into());
+            }
+            if !md.file_type().is_socket() {
+                return Err(RofiPluginError::new(&format!(
+                    "{} exists and is not a socket",
+                    listen_path
+                ))
+                .into());
+            }
+            // 能连上说明已经有 clipd 在运行, 否则是之前崩溃残留的
+            if std::os::unix::net::UnixStream::connect(listen_path).is_ok() {
+                return Err(RofiPluginError::new(&format!(
+                    "clipd is already running on {}",
+                    listen_path
+                ))
+                .into());
+            }
+            log::info!("removing stale socket {}", listen_path);
+            std::fs::remove_file(listen_path)?;
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    // bind 的时候就是 0600, 避免 chmod 之前被连上
+    let old_umask = rustix::process::umask(rustix::fs::Mode::from_raw_mode(0o177));
+    let listener = UnixListener::bind(listen_path);
+    rustix::process::umask(old_umask);
+    let listener = listener?;
+    std::fs::set_permissions(listen_path, std::fs::Permissions::from_mode(0o600))?;
+
+    return Ok(listener);
+}
+
 #[derive(Clone)]
 pub struct ClipboardServer {
-    clipboard_tx: gtk::glib::Sender<manager::ClipboardData>,
+    clipboard_tx: gtk::glib::Sender<(stroage::ClipboardEntry, clipboard::ClipboardSetTarget)>,
+    // 通知主线程退出 GTK 主循环
+    quit_tx: gtk::glib::Sender<()>,
 }
 
 unsafe impl Send for ClipboardServer {}
 
 impl ClipboardServer {
-    pub fn start_server(listen_path: String, storage: Arc<Mutex<stroage::ClipboardStorage>>) {
-        let (tx, rx) =
-            gtk::glib::MainContext::channel::<manager::ClipboardData>(gtk::glib::Priority::DEFAULT);
-        let clipboard = gtk::Clipboard::get(&gtk::gdk::SELECTION_CLIPBOARD);
+    pub fn start_server(
+        listen_path: String,
+        storage: Arc<Mutex<stroage::ClipboardStorage>>,
+        clipboard: Rc<dyn ClipboardBackend>,
+        primary: Rc<dyn ClipboardBackend>,
+    ) {
+        let (tx, rx) = gtk::glib::MainContext::channel::<(
+            stroage::ClipboardEntry,
+            clipboard::ClipboardSetTarget,
+        )>(gtk::glib::Priority::DEFAULT);
+
+        rx.attach(None, move |(entry, target)| {
+            if target != clipboard::ClipboardSetTarget::Primary {
+                clipboard.offer(entry.data.clone(), entry.formats.clone());
+            }
+            if target != clipboard::ClipboardSetTarget::Clipboard {
+                primary.offer(entry.data, entry.formats);
+            }
+            return gtk::glib::ControlFlow::Continue;
+        });
 
-        rx.attach(None, move |data| {
-            data.submit_to_clipboard(&clipboard);
+        let (quit_tx, quit_rx) =
+            gtk::glib::MainContext::channel::<()>(gtk::glib::Priority::DEFAULT);
+        quit_rx.attach(None, move |_| {
+            // 通过 GTK 主循环退出, 和收到信号时一样由 GApplication 的 shutdown 收尾
+            gtk::glib::timeout_add_local_once(SHUTDOWN_DELAY, || {
+                if let Some(app) = gtk::gio::Application::default() {
+                    app.quit();
+                }
+            });
             return gtk::glib::ControlFlow::Continue;
         });
 
-        let state = ClipboardServer { clipboard_tx: tx };
+        let state = ClipboardServer {
+            clipboard_tx: tx,
+            quit_tx,
+        };
+
+        let listener = match bind_socket(&listen_path) {
+            Ok(x) => x,
+            Err(err) => {
+                log::error!("clipboard server listen failed: {}", err);
+                std::process::exit(1);
+            }
+        };
+        let uid = rustix::process::getuid().as_raw();
 
         std::thread::spawn(move || {
             rt::System::new()
                 .block_on(
                     HttpServer::new(move || {
                         App::new()
+                            // 只处理和 clipd 相同用户的请求
+                            .wrap_fn(move |request, service| {
+                                match request.conn_data::<PeerCredentials>() {
+                                    Some(peer) if peer.uid == uid => Either::Left(
+                                        service
+                                            .call(request)
+                                            .map(|x| x.map(|x| x.map_into_left_body())),
+                                    ),
+                                    peer => {
+                                        log::error!(
+                                            "rejected request from uid {:?}, pid {:?}",
+                                            peer.map(|x| x.uid),
+                                            peer.map(|x| x.pid)
+                                        );
+                                        let response = HttpResponse::Forbidden().json(
+                                            clipboard::ClipboardErrorResult {
+                                                error: "permission denied".to_string(),
+                                            },
+                                        );
+                                        Either::Right(futures_util::future::ready(Ok(request
+                                            .into_response(response)
+                                            .map_into_right_body())))
+                                    }
+                                }
+                            })
                             .wrap(middleware::Logger::default())
+                            .wrap(middleware::DefaultHeaders::new().add((
+                                clipboard::CLIPD_API_VERSION_HEADER,
+                                clipboard::CLIPD_API_VERSION.to_string(),
+                            )))
                             .route(
                                 clipboard::CLIPBOARD_LIST,
                                 web::get().to(ClipboardServer::list_clipboard),
@@ -37,6 +169,90 @@ impl ClipboardServer {
                                 clipboard::CLIPBOARD_SET_BY_IDX,
                                 web::post().to(ClipboardServer::set_by_idx),
                             )
+                            .route(
+                                clipboard::CLIPBOARD_SET_BY_ID,
+                                web::post().to(ClipboardServer::set_by_id),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_PIN_BY_IDX,
+                                web::post().to(ClipboardServer::pin_by_idx),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_PIN_BY_ID,
+                                web::post().to(ClipboardServer::pin_by_id),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_UNPIN_BY_IDX,
+                                web::post().to(ClipboardServer::unpin_by_idx),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_UNPIN_BY_ID,
+                                web::post().to(ClipboardServer::unpin_by_id),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_GET_BY_IDX,
+                                web::post().to(ClipboardServer::get_by_idx),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_GET_BY_ID,
+                                web::post().to(ClipboardServer::get_by_id),
+                            )
+                            .route(
+                                &format!("{}/{{file}}", clipboard::CLIPBOARD_IMAGE),
+                                web::get().to(ClipboardServer::get_image),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_STATS,
+                                web::get().to(ClipboardServer::stats),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_EXPORT,
+                                web::get().to(ClipboardServer::export),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_IMPORT,
+                                web::post().to(ClipboardServer::import),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_UNLOCK,
+                                web::post().to(ClipboardServer::unlock),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_LOCK,
+                                web::post().to(ClipboardServer::lock),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_HEALTH,
+                                web::get().to(ClipboardServer::health),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_SHUTDOWN,
+                                web::post().to(ClipboardServer::shutdown),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_SEARCH,
+                                web::post().to(ClipboardServer::search),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_DELETE_BY_IDX,
+                                web::post().to(ClipboardServer::delete_by_idx),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_DELETE_BY_ID,
+                                web::post().to(ClipboardServer::delete_by_id),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_CLEAR,
+                                web::post().to(ClipboardServer::clear),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_DELETE_MATCHING,
+                                web::post().to(ClipboardServer::delete_matching),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_SUBSCRIBE,
+                                web::get().to(ClipboardServer::subscribe),
+                            )
                             .route(
                                 clipboard::CLIPBOARD_SET_TEXT,
                                 web::post().to(ClipboardServer::set_text),
@@ -45,12 +261,40 @@ impl ClipboardServer {
                                 clipboard::CLIPBOARD_GET_LATEST_TEXT,
                                 web::get().to(ClipboardServer::get_latest_text),
                             )
+                            .route(
+                                clipboard::CLIPBOARD_SET,
+                                web::post().to(ClipboardServer::set),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_SET_IMAGE,
+                                web::post().to(ClipboardServer::set_image),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_SET_URIS,
+                                web::post().to(ClipboardServer::set_uris),
+                            )
+                            .route(
+                                clipboard::CLIPBOARD_GET_LATEST,
+                                web::get().to(ClipboardServer::get_latest),
+                            )
+                            .app_data(web::PayloadConfig::new(MAX_PAYLOAD_SIZE))
                             .app_data(web::Data::from(storage.clone()))
                             .app_data(web::Data::new(Mutex::new(state.clone())))
                     })
                     // 需要 disable_signals, 不然 SIGTERM 不会立即退出, 这里不需要 Graceful shutdown
+                    .on_connect(|conn, ext| {
+                        let stream = conn.downcast_ref::<rt::net::UnixStream>();
+                        if let Some(cred) =
+                            stream.and_then(|x| rustix::net::sockopt::socket_peercred(x).ok())
+                        {
+                            ext.insert(PeerCredentials {
+                                uid: cred.uid.as_raw(),
+                                pid: cred.pid.as_raw_pid(),
+                            });
+                        }
+                    })
                     .disable_signals() 
-                    .bind_uds(listen_path)
+                    .listen_uds(listener)
                     .expect("clipboard server listen failed")
                     .workers(1)
                     .run(),
@@ -59,19 +303,159 @@ impl ClipboardServer {
         });
     }
 
+    // 记录到历史并设置到剪贴板
+    fn submit_data(&self, stroage: &mut stroage::ClipboardStorage, data: manager::ClipboardData) {
+        let entry = stroage::ClipboardEntry::new(data);
+        // 超过大小限制的不记录, 只设置到剪贴板
+        let stored = if stroage.accepts(&entry) {
+            stroage
+                .insert_entry(entry.clone())
+                .and_then(|id| stroage.position(id))
+                .and_then(|idx| stroage.get(idx))
+                .cloned()
+        } else {
+            None
+        };
+        // 和最新一条相同的时候用已有的条目, 已经停止记录的时候直接设置提交的内容
+        let entry = stored
+            .or_else(|| {
+                stroage
+                    .get_latest_entry()
+                    .filter(|e| e.data == entry.data)
+                    .cloned()
+            })
+            .unwrap_or(entry);
+        self.clipboard_tx
+            .send((entry, clipboard::ClipboardSetTarget::Clipboard))
+            .expect("clipboard data send failed");
+    }
+
     async fn list_clipboard(
         stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
     ) -> impl Responder {
         let stroage = stroage.lock().unwrap();
         return web::Json(clipboard::ClipboardListResult {
-            result: stroage
-                .list()
-                .iter()
-                .map(|e| e.to_string_with_limit(512))
-                .collect(),
+            result: stroage.list().iter().map(|e| e.to_list_item()).collect(),
         });
     }
 
+    async fn stats(stroage: web::Data<Mutex<stroage::ClipboardStorage>>) -> impl Responder {
+        let stroage = stroage.lock().unwrap();
+        return web::Json(stroage.stats());
+    }
+
+    async fn export(stroage: web::Data<Mutex<stroage::ClipboardStorage>>) -> HttpResponse {
+        let stroage = stroage.lock().unwrap();
+        return match stroage.export() {
+            Ok(data) => HttpResponse::Ok()
+                .content_type("application/x-tar")
+                .body(data),
+            Err(err) => HttpResponse::InternalServerError().json(clipboard::ClipboardErrorResult {
+                error: format!("failed to export history: {}", err),
+            }),
+        };
+    }
+
+    async fn import(
+        body: web::Bytes,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> HttpResponse {
+        let entries = match archive::import_entries(&body) {
+            Ok(x) => x,
+            Err(err) => {
+                return HttpResponse::BadRequest().json(clipboard::ClipboardErrorResult {
+                    error: format!("invalid archive: {}", err),
+                });
+            }
+        };
+
+        let imported = stroage.lock().unwrap().import(entries);
+        return HttpResponse::Ok().json(clipboard::ClipboardImportResult { imported });
+    }
+
+    async fn health() -> impl Responder {
+        return web::Json(clipboard::ClipboardHealthResult {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            api_version: clipboard::CLIPD_API_VERSION,
+            pid: std::process::id(),
+        });
+    }
+
+    // 导出历史记录并退出, 给 clipd --replace 用
+    async fn shutdown(
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+        state: web::Data<Mutex<ClipboardServer>>,
+    ) -> HttpResponse {
+        let data = match stroage.lock().unwrap().export_and_stop() {
+            Ok(x) => x,
+            Err(err) => {
+                return HttpResponse::InternalServerError().json(clipboard::ClipboardErrorResult {
+                    error: format!("failed to export history: {}", err),
+                });
+            }
+        };
+
+        log::info!("shutting down, replaced by another clipd");
+        state
+            .lock()
+            .unwrap()
+            .quit_tx
+            .send(())
+            .expect("quit message send failed");
+        return HttpResponse::Ok()
+            .content_type("application/x-tar")
+            .body(data);
+    }
+
+    async fn unlock(stroage: web::Data<Mutex<stroage::ClipboardStorage>>) -> HttpResponse {
+        let key = match file::cached_key() {
+            Some(x) => x,
+            None => {
+                return HttpResponse::BadRequest().json(clipboard::ClipboardErrorResult {
+                    error: "no cached key, unlock with clipc or notes first".to_string(),
+                });
+            }
+        };
+
+        return match stroage.lock().unwrap().unlock(key) {
+            Ok(loaded) => HttpResponse::Ok().json(clipboard::ClipboardUnlockResult { loaded }),
+            Err(err) => HttpResponse::InternalServerError().json(clipboard::ClipboardErrorResult {
+                error: format!("failed to load history: {}", err),
+            }),
+        };
+    }
+
+    async fn lock(stroage: web::Data<Mutex<stroage::ClipboardStorage>>) -> impl Responder {
+        stroage.lock().unwrap().lock();
+        return web::Json({});
+    }
+
+    async fn subscribe(stroage: web::Data<Mutex<stroage::ClipboardStorage>>) -> impl Responder {
+        let rx = stroage.lock().unwrap().subscribe();
+
+        // 每个事件一行 json, 连接一直保持到客户端断开
+        let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        let mut line = serde_json::to_vec(&event).unwrap();
+                        line.push(b'\n');
+                        return Some((Ok::<_, actix_web::Error>(web::Bytes::from(line)), rx));
+                    }
+                    // 消费太慢丢掉了一部分事件, 继续接收新的
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        log::info!("subscriber lagged, {} events skipped", n);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        return HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .streaming(stream);
+    }
+
     async fn set_by_idx(
         request: web::Json<clipboard::ClipboardSetByIdxRequest>,
         stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
@@ -83,26 +467,246 @@ impl ClipboardServer {
         if let Some(data) = stroage.move_to_front(request.idx) {
             state
                 .clipboard_tx
-                .send(data.to_owned())
+                .send((data.to_owned(), request.target))
                 .expect("clipboard data send failed");
         }
 
         return web::Json({});
     }
 
-    async fn set_text(
-        request: web::Json<clipboard::ClipboardSetTextRequest>,
+    async fn set_by_id(
+        request: web::Json<clipboard::ClipboardSetByIdRequest>,
         stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
         state: web::Data<Mutex<ClipboardServer>>,
     ) -> impl Responder {
         let mut stroage = stroage.lock().unwrap();
         let state = state.lock().unwrap();
 
-        stroage.insert_data(crate::manager::ClipboardData::Text(request.text.clone()));
-        state
-            .clipboard_tx
-            .send(stroage.get_latest_data().unwrap().to_owned())
-            .expect("clipboard data send failed");
+        if let Some(idx) = stroage.position(request.id) {
+            if let Some(data) = stroage.move_to_front(idx) {
+                state
+                    .clipboard_tx
+                    .send((data.to_owned(), request.target))
+                    .expect("clipboard data send failed");
+            }
+        }
+
+        return web::Json({});
+    }
+
+    async fn pin_by_idx(
+        request: web::Json<clipboard::ClipboardPinByIdxRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> impl Responder {
+        let mut stroage = stroage.lock().unwrap();
+        stroage.set_pinned(request.idx, true);
+
+        return web::Json({});
+    }
+
+    async fn unpin_by_idx(
+        request: web::Json<clipboard::ClipboardPinByIdxRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> impl Responder {
+        let mut stroage = stroage.lock().unwrap();
+        stroage.set_pinned(request.idx, false);
+
+        return web::Json({});
+    }
+
+    async fn pin_by_id(
+        request: web::Json<clipboard::ClipboardPinByIdRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> impl Responder {
+        let mut stroage = stroage.lock().unwrap();
+        if let Some(idx) = stroage.position(request.id) {
+            stroage.set_pinned(idx, true);
+        }
+
+        return web::Json({});
+    }
+
+    async fn unpin_by_id(
+        request: web::Json<clipboard::ClipboardPinByIdRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> impl Responder {
+        let mut stroage = stroage.lock().unwrap();
+        if let Some(idx) = stroage.position(request.id) {
+            stroage.set_pinned(idx, false);
+        }
+
+        return web::Json({});
+    }
+
+    async fn search(
+        request: web::Json<clipboard::ClipboardSearchRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> impl Responder {
+        let pattern = if request.regex {
+            request.query.clone()
+        } else {
+            regex::escape(&request.query)
+        };
+        let regex = match regex::RegexBuilder::new(&pattern)
+            .case_insensitive(request.ignore_case)
+            .build()
+        {
+            Ok(x) => x,
+            Err(err) => {
+                return HttpResponse::BadRequest().json(clipboard::ClipboardErrorResult {
+                    error: err.to_string(),
+                });
+            }
+        };
+
+        let stroage = stroage.lock().unwrap();
+        let result = stroage
+            .list()
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, e)| {
+                let text = e.data.to_searchable_text()?;
+                let matched = regex.find(&text)?;
+                Some(clipboard::ClipboardSearchHit {
+                    idx,
+                    id: e.id,
+                    snippet: make_search_snippet(&text, matched.start(), matched.end()),
+                })
+            })
+            .collect();
+
+        return HttpResponse::Ok().json(clipboard::ClipboardSearchResult { result });
+    }
+
+    fn get_result(entry: Option<&stroage::ClipboardEntry>) -> HttpResponse {
+        let entry = match entry {
+            Some(x) => x,
+            None => {
+                return HttpResponse::NotFound().json(clipboard::ClipboardErrorResult {
+                    error: "no such entry".to_string(),
+                });
+            }
+        };
+
+        let image_hash = match &entry.data {
+            manager::ClipboardData::Image(_, hash, _) => Some(hash.clone()),
+            _ => None,
+        };
+        let content = match &entry.data {
+            manager::ClipboardData::Text(text) => Some(text.clone()),
+            manager::ClipboardData::Url(urls) => Some(urls.join("\n")),
+            manager::ClipboardData::Html(html) => Some(html.clone()),
+            manager::ClipboardData::Image(..) | manager::ClipboardData::Binary(..) => None,
+        };
+
+        return HttpResponse::Ok().json(clipboard::ClipboardGetResult {
+            item: entry.to_list_item(),
+            content,
+            image_hash,
+        });
+    }
+
+    async fn get_by_idx(
+        request: web::Json<clipboard::ClipboardGetByIdxRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> HttpResponse {
+        let stroage = stroage.lock().unwrap();
+        return Self::get_result(stroage.get(request.idx));
+    }
+
+    async fn get_by_id(
+        request: web::Json<clipboard::ClipboardGetByIdRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> HttpResponse {
+        let stroage = stroage.lock().unwrap();
+        let entry = stroage
+            .position(request.id)
+            .and_then(|idx| stroage.get(idx));
+        return Self::get_result(entry);
+    }
+
+    async fn get_image(
+        file: web::Path<String>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> HttpResponse {
+        let stroage = stroage.lock().unwrap();
+        let image = file
+            .strip_suffix(".png")
+            .and_then(|hash| stroage.find_image(hash));
+
+        match image.map(|x| x.to_bytes()) {
+            Some(Ok((mime, bytes))) => HttpResponse::Ok().content_type(mime).body(bytes),
+            Some(Err(err)) => {
+                HttpResponse::InternalServerError().json(clipboard::ClipboardErrorResult {
+                    error: format!("failed to encode image: {}", err),
+                })
+            }
+            None => HttpResponse::NotFound().json(clipboard::ClipboardErrorResult {
+                error: "no such image".to_string(),
+            }),
+        }
+    }
+
+    async fn delete_by_idx(
+        request: web::Json<clipboard::ClipboardDeleteByIdxRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> impl Responder {
+        let mut stroage = stroage.lock().unwrap();
+        stroage.delete(request.idx);
+
+        return web::Json({});
+    }
+
+    async fn delete_by_id(
+        request: web::Json<clipboard::ClipboardDeleteByIdRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> impl Responder {
+        let mut stroage = stroage.lock().unwrap();
+        if let Some(idx) = stroage.position(request.id) {
+            stroage.delete(idx);
+        }
+
+        return web::Json({});
+    }
+
+    async fn clear(
+        request: web::Json<clipboard::ClipboardClearRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> impl Responder {
+        let mut stroage = stroage.lock().unwrap();
+        let deleted = stroage.clear(request.keep_pinned);
+
+        return web::Json(clipboard::ClipboardDeleteResult { deleted });
+    }
+
+    async fn delete_matching(
+        request: web::Json<clipboard::ClipboardDeleteMatchingRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+    ) -> impl Responder {
+        let regex = match regex::Regex::new(&request.regex) {
+            Ok(x) => x,
+            Err(err) => {
+                return HttpResponse::BadRequest().json(clipboard::ClipboardErrorResult {
+                    error: err.to_string(),
+                });
+            }
+        };
+
+        let mut stroage = stroage.lock().unwrap();
+        let deleted = stroage.delete_matching(&regex, request.keep_pinned);
+
+        return HttpResponse::Ok().json(clipboard::ClipboardDeleteResult { deleted });
+    }
+
+    async fn set_text(
+        request: web::Json<clipboard::ClipboardSetTextRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+        state: web::Data<Mutex<ClipboardServer>>,
+    ) -> impl Responder {
+        state.lock().unwrap().submit_data(
+            &mut stroage.lock().unwrap(),
+            manager::ClipboardData::Text(request.text.clone()),
+        );
         return web::Json({});
     }
 
@@ -118,4 +722,141 @@ impl ClipboardServer {
 
         return web::Json(clipboard::ClipboardGetLatestTextResult { result: text });
     }
+
+    // body 是原始数据, 类型由 Content-Type 决定
+    async fn set(
+        request: HttpRequest,
+        body: web::Bytes,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+        state: web::Data<Mutex<ClipboardServer>>,
+    ) -> HttpResponse {
+        let mime = request
+            .headers()
+            .get(actix_web::http::header::CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or("application/octet-stream");
+        let data = match manager::ClipboardData::from_bytes(mime, body.to_vec()) {
+            Ok(x) => x,
+            Err(err) => {
+                return HttpResponse::BadRequest().json(clipboard::ClipboardErrorResult {
+                    error: format!("invalid {} data: {}", mime, err),
+                });
+            }
+        };
+
+        state
+            .lock()
+            .unwrap()
+            .submit_data(&mut stroage.lock().unwrap(), data);
+        return HttpResponse::Ok().finish();
+    }
+
+    // body 是 PNG/JPEG 等编码过的图片
+    async fn set_image(
+        body: web::Bytes,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+        state: web::Data<Mutex<ClipboardServer>>,
+    ) -> HttpResponse {
+        let data = match manager::ClipboardData::from_image_bytes(&body) {
+            Ok(x) => x,
+            Err(err) => {
+                return HttpResponse::BadRequest().json(clipboard::ClipboardErrorResult {
+                    error: format!("invalid image: {}", err),
+                });
+            }
+        };
+
+        state
+            .lock()
+            .unwrap()
+            .submit_data(&mut stroage.lock().unwrap(), data);
+        return HttpResponse::Ok().finish();
+    }
+
+    async fn set_uris(
+        request: web::Json<clipboard::ClipboardSetUrisRequest>,
+        stroage: web::Data<Mutex<stroage::ClipboardStorage>>,
+        state: web::Data<Mutex<ClipboardServer>>,
+    ) -> HttpResponse {
+        let mut uris = Vec::new();
+        for uri in request.uris.iter() {
+            // 绝对路径转换成 file:// uri
+            if uri.starts_with('/') {
+                match gtk::glib::filename_to_uri(uri, None) {
+                    Ok(x) => uris.push(x.to_string()),
+                    Err(err) => {
+                        return HttpResponse::BadRequest().json(clipboard::ClipboardErrorResult {
+                            error: format!("invalid path {}: {}", uri, err),
+                        });
+                    }
+                }
+            } else {
+                uris.push(uri.clone());
+            }
+        }
+        if uris.is_empty() {
+            return HttpResponse::BadRequest().json(clipboard::ClipboardErrorResult {
+                error: "uris is empty".to_string(),
+            });
+        }
+
+        state.lock().unwrap().submit_data(
+            &mut stroage.lock().unwrap(),
+            manager::ClipboardData::Url(uris),
+        );
+        return HttpResponse::Ok().finish();
+    }
+
+    // 返回原始数据, 图片编码成 png
+    async fn get_latest(stroage: web::Data<Mutex<stroage::ClipboardStorage>>) -> HttpResponse {
+        let stroage = stroage.lock().unwrap();
+        let data = match stroage.get_latest_data() {
+            Some(x) => x,
+            None => {
+                return HttpResponse::Ok()
+                    .content_type("text/plain;charset=utf-8")
+                    .finish()
+            }
+        };
+
+        match data.to_bytes() {
+            Ok((mime, bytes)) => HttpResponse::Ok().content_type(mime).body(bytes),
+            Err(err) => HttpResponse::InternalServerError().json(clipboard::ClipboardErrorResult {
+                error: format!("failed to encode clipboard data: {}", err),
+            }),
+        }
+    }
+}
+
+fn make_search_snippet(text: &str, start: usize, end: usize) -> String {
+    // 命中位置前后各保留一部分上下文
+    const CONTEXT_LIMIT: usize = 40;
+    const MATCHED_LIMIT: usize = 200;
+
+    let prefix_chars = text[..start].chars().rev().collect::<Vec<_>>();
+    let prefix = prefix_chars
+        .iter()
+        .take(CONTEXT_LIMIT)
+        .rev()
+        .collect::<String>();
+    let matched = text[start..end]
+        .chars()
+        .take(MATCHED_LIMIT)
+        .collect::<String>();
+    let suffix_chars = text[end..].chars().collect::<Vec<_>>();
+    let suffix = suffix_chars.iter().take(CONTEXT_LIMIT).collect::<String>();
+
+    let mut snippet = String::new();
+    if prefix_chars.len() > CONTEXT_LIMIT {
+        snippet.push('…');
+    }
+    snippet.push_str(&html_escape::encode_text(&prefix));
+    snippet.push_str("<b>");
+    snippet.push_str(&html_escape::encode_text(&matched));
+    snippet.push_str("</b>");
+    snippet.push_str(&html_escape::encode_text(&suffix));
+    if suffix_chars.len() > CONTEXT_LIMIT {
+        snippet.push('…');
+    }
+    return snippet;
 }
@@ -1,19 +1,70 @@
+mod archive;
+mod backend;
+mod filter;
+mod gtk_backend;
+mod history;
 mod http_rpc;
 mod manager;
+mod source;
 mod stroage;
+mod thumbnail;
+mod transform;
+mod wlr_backend;
 
 use gtk::prelude::*;
 use gtk::{Application, ApplicationWindow};
 use manager::ClipboardManager;
 use rofi_toys::{clipboard, file};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 struct ClipdConfig {
     max_size: usize,
+    // 在整个历史记录里去重, 重复复制时把已有条目移到最前面, 否则只和最新一条比较
+    dedupe_history: bool,
+    // 单条记录和整个历史记录占用的字节数上限, 0 代表不限制
+    max_entry_bytes: usize,
+    max_total_bytes: usize,
+    persist_history: bool,
+    // 除了主要格式以外, 原样保存剪贴板提供的其他 target
+    capture_all_formats: bool,
+    max_format_size: usize,
+    // 是否同时记录 PRIMARY selection (鼠标选中的内容)
+    track_primary: bool,
+    // PRIMARY 的条目单独计数, 不占用 max_size
+    max_primary_size: usize,
+    primary_debounce_ms: u64,
+    // auto, gtk 或 wlr
+    backend: backend::ClipdBackendKind,
+    filter: filter::ClipdFilterConfig,
+    // 记录之前对文本做的自动处理
+    transform: transform::ClipdTransformConfig,
+}
+
+impl Default for ClipdConfig {
+    fn default() -> Self {
+        ClipdConfig {
+            max_size: 32,
+            dedupe_history: false,
+            max_entry_bytes: 64 * 1024 * 1024,
+            max_total_bytes: 256 * 1024 * 1024,
+            persist_history: true,
+            capture_all_formats: true,
+            max_format_size: 1024 * 1024,
+            track_primary: false,
+            max_primary_size: 8,
+            primary_debounce_ms: 300,
+            backend: backend::ClipdBackendKind::Auto,
+            filter: filter::ClipdFilterConfig::default(),
+            transform: transform::ClipdTransformConfig::default(),
+        }
+    }
 }
 
 fn generate_default_config() -> ClipdConfig {
-    let clipd_config = ClipdConfig { max_size: 32 };
+    let clipd_config = ClipdConfig::default();
     file::config_save_to_file(&clipd_config, "clipd").unwrap();
     return clipd_config;
 }
@@ -25,27 +76,114 @@ fn read_config() -> ClipdConfig {
     }
 }
 
+// 已经有 clipd 在运行的时候, 不带 --replace 直接退出, 否则接手它的历史记录并让它退出
+fn take_over_running_instance(replace: bool) -> Option<Vec<u8>> {
+    let client = clipboard::ClipdClient::new();
+    let health = match client.health() {
+        Ok(x) => x,
+        // 没有运行, 残留的 socket 在 listen 的时候清理
+        Err(clipboard::ClipdError::DaemonNotRunning(_)) => return None,
+        Err(err) => {
+            eprintln!("failed to check running clipd: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if !replace {
+        eprintln!(
+            "clipd {} is already running (pid {}), use --replace to replace it",
+            health.version, health.pid
+        );
+        std::process::exit(1);
+    }
+
+    log::info!("replacing clipd {} (pid {})", health.version, health.pid);
+    // 导出和停止记录是一步完成的, 不会漏掉中间复制的内容
+    let archive = match client.shutdown() {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("failed to shutdown running clipd: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // 等它退出
+    for _ in 0..50 {
+        if let Err(clipboard::ClipdError::DaemonNotRunning(_)) = client.health() {
+            return Some(archive);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    eprintln!("running clipd did not exit in time");
+    std::process::exit(1);
+}
+
 fn main() {
+    // --replace 自己处理, 其他参数交给 GApplication
+    let mut args: Vec<String> = std::env::args().collect();
+    let replace = args.iter().skip(1).any(|x| x == "--replace");
+    args.retain(|x| x != "--replace");
+    let handover = RefCell::new(take_over_running_instance(replace));
+
+    // 单实例由上面自己检查, 不让 GApplication 把 activate 转发给已经运行的实例
     let app = Application::builder()
         .application_id("com.rofi-toys.clipd")
+        .flags(gtk::gio::ApplicationFlags::NON_UNIQUE)
         .build();
 
-    app.connect_activate(|app| {
+    app.connect_activate(move |app| {
         let clipd_config = read_config();
-        let clipboard = gtk::Clipboard::get(&gtk::gdk::SELECTION_CLIPBOARD);
-        let manager =
-            ClipboardManager::new(clipd_config.max_size, clipboard::get_clipd_listen_path());
+        let (clipboard, primary) = backend::create_backends(clipd_config.backend);
+        let manager = Rc::new(ClipboardManager::new(
+            &clipd_config,
+            clipboard::get_clipd_listen_path(),
+            clipboard.clone(),
+            primary.clone(),
+        ));
 
-        clipboard.connect("owner-change", false, move |values| {
-            let clipboard = values[0]
-                .get::<gtk::Clipboard>()
-                .expect("clipboard cast failed");
+        if let Some(archive) = handover.borrow_mut().take() {
+            match manager.import_history(&archive) {
+                Ok(imported) => log::info!("took over {} entries from previous clipd", imported),
+                Err(err) => log::error!("failed to import history from previous clipd: {}", err),
+            }
+        }
 
-            manager.clipboard_on_update(clipboard);
-            return None;
-        });
+        let shutdown_manager = manager.clone();
+        app.connect_shutdown(move |_| shutdown_manager.flush_history());
+
+        let clipboard_manager = manager.clone();
+        clipboard.connect_owner_change(Box::new(move || {
+            let source = clipboard_manager.detect_source(clipboard::ClipboardSelection::Clipboard);
+            clipboard_manager.clipboard_on_update(clipboard::ClipboardSelection::Clipboard, source);
+        }));
+
+        if clipd_config.track_primary {
+            let debounce = std::time::Duration::from_millis(clipd_config.primary_debounce_ms);
+            let pending: Rc<RefCell<Option<gtk::glib::SourceId>>> = Rc::new(RefCell::new(None));
 
-        // 剪贴板需要一个窗口才能收到消息
+            primary.connect_owner_change(Box::new(move || {
+                // 拖动选择的时候会连续触发, 等选区稳定之后再记录
+                if let Some(source) = pending.borrow_mut().take() {
+                    source.remove();
+                }
+
+                // 来源在选区变化的时候就确定, 不等到 debounce 之后
+                let selection_source =
+                    manager.detect_source(clipboard::ClipboardSelection::Primary);
+                let manager = manager.clone();
+                let fired = pending.clone();
+                let source = gtk::glib::timeout_add_local_once(debounce, move || {
+                    fired.borrow_mut().take();
+                    manager.clipboard_on_update(
+                        clipboard::ClipboardSelection::Primary,
+                        selection_source,
+                    );
+                });
+                *pending.borrow_mut() = Some(source);
+            }));
+        }
+
+        // gtk backend 需要一个窗口才能收到剪贴板消息
         // 所以建一个空的窗口
         let _ = ApplicationWindow::builder()
             .application(app)
@@ -55,5 +193,14 @@ fn main() {
             .build();
     });
 
-    app.run();
+    // 历史记录是定期保存的, 收到信号时正常退出主循环, 由 shutdown 保存剩下的修改
+    for signal in [rustix::process::Signal::TERM, rustix::process::Signal::INT] {
+        let app = app.clone();
+        gtk::glib::unix_signal_add_local(signal.as_raw(), move || {
+            app.quit();
+            return gtk::glib::ControlFlow::Break;
+        });
+    }
+
+    app.run_with_args(&args);
 }
@@ -1,11 +1,36 @@
+use gtk::prelude::*;
 use md5::{Digest, Md5};
+use once_cell::sync::Lazy;
+use rofi_toys::{clipboard, rofi};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
-use crate::{http_rpc, stroage};
+use crate::backend::ClipboardBackend;
+use crate::{
+    archive, filter, history, http_rpc, source, stroage, thumbnail, transform, ClipdConfig,
+};
+
+// 历史记录的修改合并起来定期保存, 避免每次复制都在锁里重写整个文件
+const SAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// 搜索和过滤的时候每条记录都会用到, 只编译一次
+static HTML_TAG_REGEX: Lazy<regex::Regex> =
+    Lazy::new(|| regex::Regex::new(r"(?s)<[^>]*>").unwrap());
 
-#[derive(Debug)]
 pub struct ClipboardManager {
     storage: Arc<Mutex<stroage::ClipboardStorage>>,
+    clipboard: Rc<dyn ClipboardBackend>,
+    primary: Rc<dyn ClipboardBackend>,
+    filter: filter::SensitiveFilter,
+    transformer: transform::TextTransformer,
+    source: Option<source::SourceDetector>,
+    capture_all_formats: bool,
+    max_format_size: usize,
+    // 每次 owner 变化加一, 用来放弃还没读完的其他格式
+    clipboard_generation: Rc<Cell<u64>>,
+    primary_generation: Rc<Cell<u64>>,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
@@ -39,6 +64,8 @@ pub enum ClipboardData {
         String,
         chrono::DateTime<chrono::Local>,
     ),
+    // 通过 /set 设置的任意数据, (mime, data)
+    Binary(String, Vec<u8>),
 }
 
 impl ToString for ClipboardData {
@@ -58,6 +85,13 @@ impl ToString for ClipboardData {
                         .get_appropriate_unit(false)
                 )
             }
+            Self::Binary(mime, data) => {
+                format!(
+                    "[binary: {}, {}]",
+                    mime,
+                    byte_unit::Byte::from_bytes(data.len() as u128).get_appropriate_unit(false)
+                )
+            }
         }
     }
 }
@@ -69,6 +103,7 @@ impl PartialEq for ClipboardData {
             (Self::Url(t1), Self::Url(t2)) => t1 == t2,
             (Self::Html(t1), Self::Html(t2)) => t1 == t2,
             (Self::Image(_, t1, _), Self::Image(_, t2, _)) => t1 == t2,
+            (Self::Binary(m1, d1), Self::Binary(m2, d2)) => m1 == m2 && d1 == d2,
             _ => false,
         }
     }
@@ -91,7 +126,126 @@ impl ClipboardData {
                         .get_appropriate_unit(false)
                 )
             }
+            Self::Binary(mime, data) => {
+                format!(
+                    "[binary: {}, {}]",
+                    mime,
+                    byte_unit::Byte::from_bytes(data.len() as u128).get_appropriate_unit(false)
+                )
+            }
+        }
+    }
+
+    // 用于搜索的完整文本, html 去掉标签, 图片和二进制数据没有可以搜索的内容
+    pub fn to_searchable_text(&self) -> Option<String> {
+        match self {
+            Self::Text(text) => Some(text.to_owned()),
+            Self::Url(urls) => Some(urls.join("\n")),
+            Self::Html(html) => {
+                let text = HTML_TAG_REGEX.replace_all(html, "");
+                Some(html_escape::decode_html_entities(&text).to_string())
+            }
+            Self::Image(..) | Self::Binary(..) => None,
+        }
+    }
+
+    pub fn kind(&self) -> clipboard::ClipboardEntryKind {
+        match self {
+            Self::Text(_) => clipboard::ClipboardEntryKind::Text,
+            Self::Url(_) => clipboard::ClipboardEntryKind::Url,
+            Self::Html(_) => clipboard::ClipboardEntryKind::Html,
+            Self::Image(..) => clipboard::ClipboardEntryKind::Image,
+            Self::Binary(..) => clipboard::ClipboardEntryKind::Binary,
+        }
+    }
+
+    pub fn byte_length(&self) -> usize {
+        match self {
+            Self::Text(text) => text.len(),
+            Self::Url(urls) => urls.iter().map(|x| x.len()).sum(),
+            Self::Html(html) => html.len(),
+            Self::Image(image, _, _) => image.byte_length(),
+            Self::Binary(_, data) => data.len(),
+        }
+    }
+
+    pub fn char_count(&self) -> usize {
+        match self {
+            Self::Text(text) => text.chars().count(),
+            Self::Url(urls) => urls.iter().map(|x| x.chars().count()).sum(),
+            Self::Html(html) => html.chars().count(),
+            Self::Image(..) | Self::Binary(..) => 0,
+        }
+    }
+
+    // 原始数据和对应的 mime, 图片编码成 png
+    pub fn to_bytes(&self) -> anyhow::Result<(String, Vec<u8>)> {
+        return Ok(match self {
+            Self::Text(text) => (
+                "text/plain;charset=utf-8".to_string(),
+                text.as_bytes().to_vec(),
+            ),
+            Self::Url(urls) => ("text/uri-list".to_string(), urls.join("\r\n").into_bytes()),
+            Self::Html(html) => ("text/html".to_string(), html.as_bytes().to_vec()),
+            Self::Image(image, _, _) => {
+                ("image/png".to_string(), image.save_to_bufferv("png", &[])?)
+            }
+            Self::Binary(mime, data) => (mime.clone(), data.clone()),
+        });
+    }
+
+    // 编码过的图片, 格式由 gdk-pixbuf 自己识别
+    pub fn from_image_bytes(data: &[u8]) -> anyhow::Result<ClipboardData> {
+        let loader = gtk::gdk_pixbuf::PixbufLoader::new();
+        loader.write(data)?;
+        loader.close()?;
+        match loader.pixbuf() {
+            Some(pixbuf) => {
+                let image_hash = ClipboardData::calc_image_hash(&pixbuf);
+                Ok(ClipboardData::Image(
+                    pixbuf,
+                    image_hash,
+                    chrono::Local::now(),
+                ))
+            }
+            None => Err(rofi::RofiPluginError::new("no image decoded").into()),
+        }
+    }
+
+    // to_bytes 的反向操作, 不认识的 mime 和解码不了的图片保存成 Binary
+    pub fn from_bytes(mime: &str, data: Vec<u8>) -> anyhow::Result<ClipboardData> {
+        let essence = mime
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+
+        if essence.starts_with("image/") {
+            // gdk-pixbuf 没有对应 loader 的格式 (比如 webp) 原样保存
+            return Ok(match ClipboardData::from_image_bytes(&data) {
+                Ok(image) => image,
+                Err(_) => ClipboardData::Binary(essence, data),
+            });
         }
+
+        let text = match String::from_utf8(data) {
+            Ok(text) => text,
+            // 不是合法的 utf-8 就不能当成文本
+            Err(err) => return Ok(ClipboardData::Binary(essence, err.into_bytes())),
+        };
+        return Ok(match essence.as_str() {
+            "text/html" => ClipboardData::Html(text),
+            "text/uri-list" => ClipboardData::Url(
+                text.lines()
+                    .map(|x| x.trim())
+                    .filter(|x| !x.is_empty() && !x.starts_with('#'))
+                    .map(|x| x.to_string())
+                    .collect(),
+            ),
+            _ if essence.starts_with("text/") || essence.is_empty() => ClipboardData::Text(text),
+            _ => ClipboardData::Binary(essence, text.into_bytes()),
+        });
     }
 
     pub fn calc_image_hash(pixbuf: &gtk::gdk_pixbuf::Pixbuf) -> String {
@@ -105,96 +259,269 @@ impl ClipboardData {
 
 unsafe impl Send for ClipboardData {}
 
-impl ClipboardData {
-    pub fn submit_to_clipboard(self, clipboard: &gtk::Clipboard) {
-        match self {
-            ClipboardData::Text(text) => {
-                clipboard.set_text(&text);
+// 原样保存的剪贴板 target, 恢复时按原样重新提供
+#[derive(Debug, Clone)]
+pub struct ClipboardFormat {
+    pub target: String,
+    pub data: Vec<u8>,
+}
+
+// 这些 target 是 X11 selection 协议本身的, 不是数据
+const META_TARGETS: [&str; 7] = [
+    "TARGETS",
+    "TIMESTAMP",
+    "MULTIPLE",
+    "SAVE_TARGETS",
+    "DELETE",
+    "INSERT_PROPERTY",
+    "INSERT_SELECTION",
+];
+
+// 依次异步读取剩下的 target, 不阻塞主循环, owner 变化之后就放弃
+struct FormatFetch {
+    clipboard: Rc<dyn ClipboardBackend>,
+    generation: Rc<Cell<u64>>,
+    started_generation: u64,
+    // 倒序排列, 从末尾开始读
+    pending: Vec<String>,
+    formats: Vec<ClipboardFormat>,
+    max_format_size: usize,
+    on_done: Box<dyn FnOnce(Vec<ClipboardFormat>)>,
+}
+
+impl FormatFetch {
+    fn next(mut self) {
+        if self.generation.get() != self.started_generation {
+            log::trace!("clipboard owner changed, stop capturing formats");
+            return;
+        }
+        let target = match self.pending.pop() {
+            Some(x) => x,
+            None => return (self.on_done)(self.formats),
+        };
+
+        let clipboard = self.clipboard.clone();
+        clipboard.read_async(
+            &target.clone(),
+            Box::new(move |data| {
+                match data {
+                    Some(data) if data.len() > self.max_format_size => {
+                        log::info!("skip large clipboard target {}: {}", target, data.len());
+                    }
+                    Some(data) => self.formats.push(ClipboardFormat { target, data }),
+                    None => {}
+                }
+                self.next();
+            }),
+        );
+    }
+}
+
+impl ClipboardManager {
+    pub fn new(
+        config: &ClipdConfig,
+        listen_path: String,
+        clipboard: Rc<dyn ClipboardBackend>,
+        primary: Rc<dyn ClipboardBackend>,
+    ) -> ClipboardManager {
+        let history = if config.persist_history {
+            match history::ClipboardHistory::new() {
+                Ok(x) => Some(x),
+                Err(err) => {
+                    log::error!("failed to open clipboard history: {:?}", err);
+                    None
+                }
             }
-            ClipboardData::Url(urls) => {
-                clipboard.set_with_data(
-                    vec![
-                        gtk::TargetEntry::new(
-                            "text/uri-list",
-                            gtk::TargetFlags::OTHER_APP,
-                            ClipboardDataType::Url as u32,
-                        ),
-                        gtk::TargetEntry::new(
-                            "UTF8_STRING",
-                            gtk::TargetFlags::OTHER_APP,
-                            ClipboardDataType::Text as u32,
-                        ),
-                    ]
-                    .as_slice(),
-                    move |_, data, data_type| match ClipboardDataType::from_u32(data_type) {
-                        ClipboardDataType::Url => {
-                            data.set_uris(
-                                urls.iter()
-                                    .map(|u| u.as_str())
-                                    .collect::<Vec<_>>()
-                                    .as_slice(),
-                            );
-                        }
-                        ClipboardDataType::Text => {
-                            data.set_text(&urls.join("\n"));
-                        }
-                        _ => {}
-                    },
-                );
+        } else {
+            None
+        };
+        // Wayland 下 X11 只能看到 XWayland 的窗口, 结果不可信, 干脆不记录来源
+        let source = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            None
+        } else {
+            match source::SourceDetector::new() {
+                Ok(x) => Some(x),
+                Err(err) => {
+                    log::error!("failed to connect to X11 for source detection: {:?}", err);
+                    None
+                }
             }
-            ClipboardData::Html(html) => {
-                clipboard.set_with_data(
-                    vec![
-                        gtk::TargetEntry::new(
-                            "text/html",
-                            gtk::TargetFlags::OTHER_APP,
-                            ClipboardDataType::Html as u32,
-                        ),
-                        gtk::TargetEntry::new(
-                            "UTF8_STRING",
-                            gtk::TargetFlags::OTHER_APP,
-                            ClipboardDataType::Text as u32,
-                        ),
-                    ]
-                    .as_slice(),
-                    move |_, data, data_type| match ClipboardDataType::from_u32(data_type) {
-                        ClipboardDataType::Html | ClipboardDataType::Text => {
-                            data.set_text(&html);
-                        }
-                        _ => {}
-                    },
-                );
+        };
+
+        let thumbnails = match thumbnail::ThumbnailCache::new() {
+            // 缩略图没法加密, 开启加密时不生成, 并清掉之前的
+            Ok(x) if history.as_ref().is_some_and(|x| x.is_encrypted()) => {
+                if let Err(err) = x.cleanup(&HashSet::new()) {
+                    log::error!("failed to cleanup thumbnails: {:?}", err);
+                }
+                None
             }
-            ClipboardData::Image(image, _, _) => {
-                clipboard.set_image(&image);
+            Ok(x) => Some(x),
+            Err(err) => {
+                log::error!("failed to open thumbnail cache: {:?}", err);
+                None
             }
+        };
+
+        let storage = Arc::new(Mutex::new(stroage::ClipboardStorage::new(
+            stroage::ClipboardLimits {
+                max_size: config.max_size,
+                max_primary_size: config.max_primary_size,
+                max_entry_bytes: config.max_entry_bytes,
+                max_total_bytes: config.max_total_bytes,
+            },
+            config.dedupe_history,
+            history,
+            thumbnails,
+        )));
+        http_rpc::ClipboardServer::start_server(
+            listen_path,
+            storage.clone(),
+            clipboard.clone(),
+            primary.clone(),
+        );
+
+        let flush_storage = storage.clone();
+        gtk::glib::timeout_add_local(SAVE_INTERVAL, move || {
+            flush_storage.lock().unwrap().flush();
+            return gtk::glib::ControlFlow::Continue;
+        });
+
+        return ClipboardManager {
+            storage,
+            clipboard,
+            primary,
+            filter: filter::SensitiveFilter::new(&config.filter),
+            transformer: transform::TextTransformer::new(&config.transform),
+            source,
+            capture_all_formats: config.capture_all_formats,
+            max_format_size: config.max_format_size,
+            clipboard_generation: Rc::new(Cell::new(0)),
+            primary_generation: Rc::new(Cell::new(0)),
+        };
+    }
+
+    // --replace 时从之前的 clipd 接手的历史记录
+    pub fn import_history(&self, data: &[u8]) -> anyhow::Result<usize> {
+        let entries = archive::import_entries(data)?;
+        return Ok(self.storage.lock().unwrap().import(entries));
+    }
+
+    // 退出前把还没保存的修改写到磁盘上
+    pub fn flush_history(&self) {
+        self.storage.lock().unwrap().flush();
+    }
+
+    fn backend(&self, selection: clipboard::ClipboardSelection) -> &Rc<dyn ClipboardBackend> {
+        match selection {
+            clipboard::ClipboardSelection::Clipboard => &self.clipboard,
+            clipboard::ClipboardSelection::Primary => &self.primary,
         }
     }
-}
 
-impl ClipboardManager {
-    pub fn new(max_size: usize, listen_path: String) -> ClipboardManager {
-        let storage = Arc::new(Mutex::new(stroage::ClipboardStorage::new(max_size)));
-        http_rpc::ClipboardServer::start_server(listen_path, storage.clone());
+    fn generation(&self, selection: clipboard::ClipboardSelection) -> &Rc<Cell<u64>> {
+        match selection {
+            clipboard::ClipboardSelection::Clipboard => &self.clipboard_generation,
+            clipboard::ClipboardSelection::Primary => &self.primary_generation,
+        }
+    }
+
+    fn wait_for_html(clipboard: &dyn ClipboardBackend) -> Option<String> {
+        let html = clipboard.read("text/html").filter(|x| !x.is_empty());
+
+        if let Some(html) = html {
+            // firefox 之类的会用带 BOM 的 UTF-16
+            if html.starts_with(&[0xff, 0xfe]) {
+                let utf16 = html[2..]
+                    .chunks_exact(2)
+                    .map(|x| u16::from_le_bytes([x[0], x[1]]))
+                    .collect::<Vec<_>>();
+                return Some(String::from_utf16_lossy(&utf16));
+            }
+            return Some(String::from_utf8_lossy(&html).to_string());
+        }
 
-        return ClipboardManager { storage };
+        // 拿不到原始 html 的话退化成纯文本
+        return clipboard.read_text();
     }
 
-    pub fn clipboard_on_update(&self, clipboard: gtk::Clipboard) {
+    // 在后台读取其他 target, 读完之后补充到 id 对应的条目上
+    fn capture_formats(
+        &self,
+        selection: clipboard::ClipboardSelection,
+        id: u64,
+        targets: &[String],
+    ) {
+        if !self.capture_all_formats {
+            return;
+        }
+
+        let pending = targets
+            .iter()
+            .rev()
+            // 图片由 pixbuf 负责转换, 没必要每种格式都存一份
+            .filter(|x| !META_TARGETS.contains(&x.as_str()) && !x.starts_with("image/"))
+            .cloned()
+            .collect();
+        let storage = self.storage.clone();
+        let generation = self.generation(selection).clone();
+
+        FormatFetch {
+            clipboard: self.backend(selection).clone(),
+            started_generation: generation.get(),
+            generation,
+            pending,
+            formats: Vec::new(),
+            max_format_size: self.max_format_size,
+            on_done: Box::new(move |formats| {
+                if !formats.is_empty() {
+                    storage.lock().unwrap().set_formats(id, formats);
+                }
+            }),
+        }
+        .next();
+    }
+
+    // 复制之后来源窗口可能很快就关掉了, 或者剪贴板又换了主人, 需要在 owner 变化的时候马上调用
+    pub fn detect_source(
+        &self,
+        selection: clipboard::ClipboardSelection,
+    ) -> Option<clipboard::ClipboardSource> {
+        return self.source.as_ref().and_then(|x| x.detect(selection));
+    }
+
+    pub fn clipboard_on_update(
+        &self,
+        selection: clipboard::ClipboardSelection,
+        source: Option<clipboard::ClipboardSource>,
+    ) {
         log::trace!("updating");
 
-        let targets = clipboard.wait_for_targets();
+        let generation = self.generation(selection);
+        generation.set(generation.get() + 1);
 
-        if let Some(atoms) = targets {
-            log::trace!(
-                "types, {:?}",
-                atoms.iter().map(|x| x.name()).collect::<Vec<_>>()
-            );
+        if let Some(source) = source.as_ref().filter(|x| self.filter.is_ignored_app(x)) {
+            log::info!("ignored clipboard data from {}", source.class);
+            return;
+        }
 
-            let mut data_types: Vec<ClipboardDataType> = atoms
+        let clipboard = self.backend(selection);
+        let targets = clipboard.targets();
+
+        if let Some(target_names) = targets {
+            log::trace!("types, {:?}", target_names);
+
+            let sensitive_targets = self.filter.is_sensitive_targets(&target_names);
+            if sensitive_targets && self.filter.expire_secs == 0 {
+                // 密码管理器之类的内容, 直接跳过, 连读都不读
+                log::info!("ignored sensitive clipboard data: {:?}", target_names);
+                return;
+            }
+
+            let mut data_types: Vec<ClipboardDataType> = target_names
                 .iter()
                 .map(|x| {
-                    let atom_name = x.name().to_lowercase();
+                    let atom_name = x.to_lowercase();
                     match atom_name.as_str() {
                         "utf8_string" | "string" => ClipboardDataType::Text,
                         "text/uri-list" => ClipboardDataType::Url,
@@ -217,33 +544,26 @@ impl ClipboardManager {
 
             if curr_data_type == ClipboardDataType::None {
                 // 如果是 None, 代表无法处理的剪贴板数据
-                log::error!(
-                    "unexpeced data types: {:?}",
-                    atoms.iter().map(|x| x.name()).collect::<Vec<_>>()
-                );
+                log::error!("unexpeced data types: {:?}", target_names);
                 return;
             }
 
             // 正常处理数据
             let data = match curr_data_type {
-                ClipboardDataType::Text => clipboard
-                    .wait_for_text()
-                    .map(|x| ClipboardData::Text(x.to_string())),
+                ClipboardDataType::Text => clipboard.read_text().map(ClipboardData::Text),
                 ClipboardDataType::Url => {
-                    let urls = clipboard.wait_for_uris();
+                    let urls = clipboard.read_uris();
                     if urls.len() > 0 {
-                        Some(ClipboardData::Url(
-                            urls.iter().map(|x| x.to_string()).collect::<Vec<_>>(),
-                        ))
+                        Some(ClipboardData::Url(urls))
                     } else {
                         None
                     }
                 }
-                ClipboardDataType::Html => clipboard
-                    .wait_for_text()
-                    .map(|x| ClipboardData::Html(x.to_string())),
+                ClipboardDataType::Html => {
+                    Self::wait_for_html(clipboard.as_ref()).map(ClipboardData::Html)
+                }
                 ClipboardDataType::Image => {
-                    if let Some(pixbuf) = clipboard.wait_for_image() {
+                    if let Some(pixbuf) = clipboard.read_image() {
                         let image_hash = ClipboardData::calc_image_hash(&pixbuf);
                         Some(ClipboardData::Image(
                             pixbuf,
@@ -261,26 +581,122 @@ impl ClipboardManager {
                 data
             } else {
                 // 如果是 None, 代表获取剪贴板数据失败
-                log::error!(
-                    "failed to fetch clipboard data: {:?}",
-                    atoms.iter().map(|x| x.name()).collect::<Vec<_>>()
-                );
+                log::error!("failed to fetch clipboard data: {:?}", target_names);
                 return;
             };
 
-            let mut storage = self.storage.lock().unwrap();
-            storage.insert_data(data);
-        } else {
+            let sensitive = sensitive_targets || self.filter.is_sensitive_data(&data);
+            if sensitive && self.filter.expire_secs == 0 {
+                log::info!("ignored sensitive clipboard data: {:?}", target_names);
+                return;
+            }
+
+            // 敏感内容不做处理
+            let (data, capture_formats) = match self.transformer.transform(&data) {
+                Some(transformed) if !sensitive => {
+                    log::info!("transformed clipboard data: {:?}", target_names);
+                    // PRIMARY 跟着选区走, 重新设置会清掉应用里的选区, 只处理 CLIPBOARD
+                    if self.transformer.update_clipboard
+                        && selection == clipboard::ClipboardSelection::Clipboard
+                    {
+                        // 重新设置会再触发一次 update, 处理结果不变, 和最新一条重复所以不会再次插入
+                        clipboard.offer(transformed.clone(), Vec::new());
+                    }
+                    // 其他格式里还是处理前的内容, 恢复的时候会覆盖掉处理结果, 不保存
+                    (transformed, false)
+                }
+                _ => (data, true),
+            };
+
+            let mut entry = stroage::ClipboardEntry::new(data);
+            entry.selection = selection;
+            entry.targets = target_names.clone();
+            entry.source = source;
+            if sensitive {
+                entry.expires_at = Some(
+                    chrono::Local::now()
+                        + chrono::Duration::seconds(self.filter.expire_secs as i64),
+                );
+            }
+            let id = self.storage.lock().unwrap().insert_entry(entry);
+
+            if sensitive {
+                let storage = self.storage.clone();
+                gtk::glib::timeout_add_seconds_local_once(self.filter.expire_secs, move || {
+                    storage.lock().unwrap().remove_expired();
+                });
+            }
+            if let Some(id) = id.filter(|_| capture_formats) {
+                self.capture_formats(selection, id, &target_names);
+            }
+        } else if selection == clipboard::ClipboardSelection::Clipboard {
             // = None, 代表目标应用关闭, 需要恢复之前的剪贴板内容
-            // 目前还存在一个问题是, 恢复的设置会触发自己的 update, 造成可能的性能浪费
+            // PRIMARY 本来就是跟着选区走的, 不需要恢复
+            // gtk backend 下恢复的设置会触发自己的 update, 造成可能的性能浪费
             // 不过在 gtk 层面好像没有地方可以解决这个问题
             log::trace!("tracing");
 
             let storage = self.storage.lock().unwrap();
-            if let Some(lastest_data) = storage.get_latest_data() {
-                let lastest_data = lastest_data.to_owned();
-                lastest_data.submit_to_clipboard(&clipboard);
+            if let Some(entry) = storage.get_restorable_entry() {
+                let entry = entry.to_owned();
+                clipboard.offer(entry.data, entry.formats);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_bytes_text() {
+        let data =
+            ClipboardData::from_bytes("text/plain;charset=utf-8", b"hello".to_vec()).unwrap();
+        assert_eq!(data, ClipboardData::Text("hello".to_string()));
+        let data = ClipboardData::from_bytes("", b"hello".to_vec()).unwrap();
+        assert_eq!(data, ClipboardData::Text("hello".to_string()));
+    }
+
+    #[test]
+    fn from_bytes_html() {
+        let data = ClipboardData::from_bytes("Text/HTML", b"<b>hi</b>".to_vec()).unwrap();
+        assert_eq!(data, ClipboardData::Html("<b>hi</b>".to_string()));
+    }
+
+    #[test]
+    fn from_bytes_uri_list() {
+        let data = ClipboardData::from_bytes(
+            "text/uri-list",
+            b"# comment\r\nfile:///a\r\n\r\nhttps://example.com\r\n".to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            data,
+            ClipboardData::Url(vec![
+                "file:///a".to_string(),
+                "https://example.com".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn from_bytes_binary() {
+        let data = ClipboardData::from_bytes("text/plain", vec![0xff, 0xfe]).unwrap();
+        assert_eq!(
+            data,
+            ClipboardData::Binary("text/plain".to_string(), vec![0xff, 0xfe])
+        );
+        let data = ClipboardData::from_bytes("application/pdf", b"%PDF".to_vec()).unwrap();
+        assert_eq!(
+            data,
+            ClipboardData::Binary("application/pdf".to_string(), b"%PDF".to_vec())
+        );
+        // 解码不了的图片原样保存
+        let data = ClipboardData::from_bytes("image/webp", b"not an image".to_vec()).unwrap();
+        assert_eq!(
+            data,
+            ClipboardData::Binary("image/webp".to_string(), b"not an image".to_vec())
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use x11rb::connection::Connection;
+use x11rb::properties::WmClass;
+use x11rb::protocol::res::{ClientIdMask, ClientIdSpec, ConnectionExt as _};
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt, Window};
+use x11rb::rust_connection::RustConnection;
+
+use rofi_toys::clipboard;
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        CLIPBOARD,
+        UTF8_STRING,
+        _NET_CLIENT_LIST,
+        _NET_WM_PID,
+        _NET_WM_NAME,
+    }
+}
+
+// 通过 X11 找到复制的来源窗口, Wayland 下没有通用的办法, 不使用
+pub struct SourceDetector {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+}
+
+impl SourceDetector {
+    pub fn new() -> anyhow::Result<SourceDetector> {
+        let (conn, screen_num) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&conn)?.reply()?;
+
+        return Ok(SourceDetector { conn, root, atoms });
+    }
+
+    // (instance, class)
+    fn wm_class(&self, window: Window) -> Option<(String, String)> {
+        let wm_class = WmClass::get(&self.conn, window).ok()?.reply().ok()??;
+        return Some((
+            String::from_utf8_lossy(wm_class.instance()).to_string(),
+            String::from_utf8_lossy(wm_class.class()).to_string(),
+        ));
+    }
+
+    fn title(&self, window: Window) -> String {
+        let properties = [
+            (self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING),
+            (AtomEnum::WM_NAME.into(), AtomEnum::STRING.into()),
+        ];
+        for (property, property_type) in properties {
+            let reply = self
+                .conn
+                .get_property(false, window, property, property_type, 0, 1024)
+                .ok()
+                .and_then(|x| x.reply().ok());
+            if let Some(reply) = reply.filter(|x| !x.value.is_empty()) {
+                return String::from_utf8_lossy(&reply.value).to_string();
+            }
+        }
+        return String::new();
+    }
+
+    fn cardinals(&self, window: Window, property: u32, property_type: AtomEnum) -> Vec<u32> {
+        let reply = self
+            .conn
+            .get_property(false, window, property, property_type, 0, u32::MAX)
+            .ok()
+            .and_then(|x| x.reply().ok());
+        return match reply.as_ref().and_then(|x| x.value32()) {
+            Some(values) => values.collect(),
+            None => Vec::new(),
+        };
+    }
+
+    // 通过 X-Resource 扩展拿到持有窗口的客户端的 pid, 本地的客户端才有
+    fn client_pid(&self, window: Window) -> Option<u32> {
+        let spec = ClientIdSpec {
+            client: window,
+            mask: ClientIdMask::LOCAL_CLIENT_PID,
+        };
+        let reply = self.conn.res_query_client_ids(&[spec]).ok()?.reply().ok()?;
+        return reply
+            .ids
+            .iter()
+            .find(|x| x.spec.mask == ClientIdMask::LOCAL_CLIENT_PID)
+            .and_then(|x| x.value.first().copied());
+    }
+
+    // 同一个进程的顶层窗口, 按 _NET_WM_PID 匹配
+    fn client_windows(&self, pid: u32) -> Vec<Window> {
+        return self
+            .client_list()
+            .into_iter()
+            .filter(|window| {
+                self.cardinals(*window, self.atoms._NET_WM_PID, AtomEnum::CARDINAL)
+                    .first()
+                    == Some(&pid)
+            })
+            .collect();
+    }
+
+    fn client_list(&self) -> Vec<Window> {
+        return self.cardinals(self.root, self.atoms._NET_CLIENT_LIST, AtomEnum::WINDOW);
+    }
+
+    pub fn detect(
+        &self,
+        selection: clipboard::ClipboardSelection,
+    ) -> Option<clipboard::ClipboardSource> {
+        let selection_atom = match selection {
+            clipboard::ClipboardSelection::Clipboard => self.atoms.CLIPBOARD,
+            clipboard::ClipboardSelection::Primary => AtomEnum::PRIMARY.into(),
+        };
+        let owner = self
+            .conn
+            .get_selection_owner(selection_atom)
+            .ok()?
+            .reply()
+            .ok()?
+            .owner;
+        if owner == x11rb::NONE {
+            return None;
+        }
+
+        // 恢复剪贴板之类的操作是 clipd 自己持有的, 没有来源
+        let pid = self.client_pid(owner);
+        if pid == Some(std::process::id()) {
+            return None;
+        }
+
+        if let Some((instance, class)) = self.wm_class(owner) {
+            return Some(clipboard::ClipboardSource {
+                class,
+                instance,
+                title: self.title(owner),
+            });
+        }
+
+        // 很多应用用隐藏的窗口持有剪贴板, 这种窗口没有 WM_CLASS, 找同一个进程的顶层窗口
+        // 找不到的话宁可不记录, 也不去猜
+        let windows = self.client_windows(pid?);
+        let (instance, class) = self.wm_class(*windows.first()?)?;
+        // 有多个窗口的时候不知道是哪一个, 不记录标题
+        let title = match windows.as_slice() {
+            [window] => self.title(*window),
+            _ => String::new(),
+        };
+        return Some(clipboard::ClipboardSource {
+            class,
+            instance,
+            title,
+        });
+    }
+}
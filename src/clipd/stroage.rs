@@ -1,54 +1,802 @@
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
 
-use crate::manager;
+use crate::{archive, history, manager, thumbnail};
+use rofi_toys::{clipboard, file};
 
 pub const UNLIMITED_SIZE: usize = 0;
 
+// clear 和 delete_matching 删除的条件
+#[derive(Debug)]
+enum Removal {
+    All {
+        keep_pinned: bool,
+    },
+    Matching {
+        regex: regex::Regex,
+        keep_pinned: bool,
+    },
+}
+
+impl Removal {
+    fn matches(&self, entry: &ClipboardEntry) -> bool {
+        match self {
+            Removal::All { keep_pinned } => !(*keep_pinned && entry.pinned),
+            Removal::Matching { regex, keep_pinned } => {
+                if *keep_pinned && entry.pinned {
+                    return false;
+                }
+                match entry.data.to_searchable_text() {
+                    Some(text) => regex.is_match(&text),
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+// 旧的历史记录没有 id, 接着最大的 id 补上, 返回下一个可用的 id
+fn assign_missing_ids(datas: &mut VecDeque<ClipboardEntry>, saved_next_id: u64) -> u64 {
+    let mut next_id = u64::max(
+        saved_next_id,
+        datas.iter().map(|e| e.id).max().unwrap_or(0) + 1,
+    );
+    for entry in datas.iter_mut().filter(|e| e.id == 0) {
+        entry.id = next_id;
+        next_id += 1;
+    }
+    return next_id;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClipboardLimits {
+    // 未固定条目的数量, CLIPBOARD 和 PRIMARY 分开计算
+    pub max_size: usize,
+    pub max_primary_size: usize,
+    pub max_entry_bytes: usize,
+    pub max_total_bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClipboardEntry {
+    // 由 ClipboardStorage 分配, 单调递增, 0 代表还没有分配
+    pub id: u64,
+    pub first_seen: chrono::DateTime<chrono::Local>,
+    pub last_used: chrono::DateTime<chrono::Local>,
+    pub use_count: u32,
+    pub data: manager::ClipboardData,
+    pub selection: clipboard::ClipboardSelection,
+    // 复制时剪贴板提供的所有 target
+    pub targets: Vec<String>,
+    // 原样保存的其他格式, 恢复的时候一起提供
+    pub formats: Vec<manager::ClipboardFormat>,
+    // 固定的条目不会被淘汰, 也不计入 max_size
+    pub pinned: bool,
+    // 敏感内容的过期时间, 这类条目不会被持久化
+    pub expires_at: Option<chrono::DateTime<chrono::Local>>,
+    // 图片的缩略图, 由 ClipboardStorage 生成
+    pub thumbnail: Option<PathBuf>,
+    // 复制来源的窗口
+    pub source: Option<clipboard::ClipboardSource>,
+}
+
+impl ClipboardEntry {
+    pub fn new(data: manager::ClipboardData) -> ClipboardEntry {
+        return ClipboardEntry {
+            id: 0,
+            first_seen: chrono::Local::now(),
+            last_used: chrono::Local::now(),
+            use_count: 1,
+            data,
+            selection: clipboard::ClipboardSelection::Clipboard,
+            targets: Vec::new(),
+            formats: Vec::new(),
+            pinned: false,
+            expires_at: None,
+            thumbnail: None,
+            source: None,
+        };
+    }
+
+    // 数据加上原样保存的格式, 图片按解码后的像素数据计算
+    pub fn memory_size(&self) -> usize {
+        self.data.byte_length() + self.formats.iter().map(|x| x.data.len()).sum::<usize>()
+    }
+
+    pub fn to_list_item(&self) -> clipboard::ClipboardListItem {
+        return clipboard::ClipboardListItem {
+            id: self.id,
+            kind: self.data.kind(),
+            selection: self.selection,
+            pinned: self.pinned,
+            byte_length: self.data.byte_length(),
+            char_count: self.data.char_count(),
+            first_seen: self.first_seen.timestamp(),
+            last_used: self.last_used.timestamp(),
+            use_count: self.use_count,
+            targets: self.targets.clone(),
+            preview: self.data.to_string_with_limit(512),
+            thumbnail: self
+                .thumbnail
+                .as_ref()
+                .map(|x| x.to_string_lossy().to_string()),
+            source: self.source.clone(),
+        };
+    }
+}
+
 #[derive(Debug)]
 pub struct ClipboardStorage {
-    max_size: usize,
+    limits: ClipboardLimits,
+    dedupe_history: bool,
+    next_id: u64,
 
-    datas: VecDeque<manager::ClipboardData>,
+    datas: VecDeque<ClipboardEntry>,
+    // None 代表不需要持久化
+    history: Option<history::ClipboardHistory>,
+    // 有还没写到磁盘上的修改, 由 flush 定期保存
+    dirty: bool,
+    // 被新的 clipd 接手之后不再记录, 也不再写磁盘
+    stopped: bool,
+    // 磁盘上的历史记录已经读进内存, 启动时还没解锁的话要等到 unlock 才读取
+    loaded: bool,
+    // 还没读取历史记录时的清空和按内容删除, 解锁后对磁盘上的记录再执行一遍
+    pending_removals: Vec<Removal>,
+    thumbnails: Option<thumbnail::ThumbnailCache>,
+    // /subscribe 的订阅者
+    events: tokio::sync::broadcast::Sender<clipboard::ClipboardEvent>,
 }
 
 impl ClipboardStorage {
-    pub fn new(max_size: usize) -> ClipboardStorage {
-        return ClipboardStorage {
-            max_size,
-            datas: VecDeque::new(),
+    pub fn new(
+        limits: ClipboardLimits,
+        dedupe_history: bool,
+        history: Option<history::ClipboardHistory>,
+        thumbnails: Option<thumbnail::ThumbnailCache>,
+    ) -> ClipboardStorage {
+        let (mut datas, saved_next_id, loaded) = if let Some(history) = &history {
+            match history.load() {
+                Ok((datas, next_id)) => (datas, next_id, true),
+                Err(err) => {
+                    log::error!("failed to load clipboard history: {:?}", err);
+                    (VecDeque::new(), 0, false)
+                }
+            }
+        } else {
+            (VecDeque::new(), 0, true)
+        };
+        let next_id = assign_missing_ids(&mut datas, saved_next_id);
+
+        if let Some(thumbnails) = &thumbnails {
+            let mut hashes = HashSet::new();
+            for entry in datas.iter_mut() {
+                if let manager::ClipboardData::Image(pixbuf, hash, _) = &entry.data {
+                    entry.thumbnail = thumbnails.get_or_create(pixbuf, hash);
+                    hashes.insert(hash.clone());
+                }
+            }
+            // 之后只在删除图片条目的时候清理, 启动时清掉上次残留的
+            if let Err(err) = thumbnails.cleanup(&hashes) {
+                log::error!("failed to cleanup thumbnails: {:?}", err);
+            }
+        }
+
+        let mut storage = ClipboardStorage {
+            limits,
+            dedupe_history,
+            next_id,
+            datas,
+            history,
+            dirty: false,
+            stopped: false,
+            loaded,
+            pending_removals: Vec::new(),
+            thumbnails,
+            events: tokio::sync::broadcast::channel(64).0,
+        };
+        // 限制可能被改小了
+        storage.evict();
+        return storage;
+    }
+
+    fn evict(&mut self) {
+        let mut removed = Vec::new();
+
+        // PRIMARY 单独计数, 鼠标选中的内容不会把复制的内容挤掉
+        for (selection, max_size) in [
+            (
+                clipboard::ClipboardSelection::Clipboard,
+                self.limits.max_size,
+            ),
+            (
+                clipboard::ClipboardSelection::Primary,
+                self.limits.max_primary_size,
+            ),
+        ] {
+            if max_size == UNLIMITED_SIZE {
+                continue;
+            }
+            let evictable = |e: &ClipboardEntry| !e.pinned && e.selection == selection;
+
+            // 从后往前去掉多余的未固定数据
+            let mut unpinned_count = self.datas.iter().filter(|e| evictable(e)).count();
+            let mut idx = self.datas.len();
+            while unpinned_count > max_size && idx > 0 {
+                idx -= 1;
+                if evictable(&self.datas[idx]) {
+                    removed.extend(self.datas.remove(idx));
+                    unpinned_count -= 1;
+                }
+            }
+        }
+
+        if self.limits.max_total_bytes != UNLIMITED_SIZE {
+            // 同样从后往前淘汰, 最新的一条保留, 固定的条目也不计入淘汰
+            let mut total_bytes = self.memory_usage();
+            let mut idx = self.datas.len();
+            while total_bytes > self.limits.max_total_bytes && idx > 1 {
+                idx -= 1;
+                if !self.datas[idx].pinned {
+                    let entry = self.datas.remove(idx).unwrap();
+                    total_bytes -= entry.memory_size();
+                    removed.push(entry);
+                }
+            }
+        }
+
+        self.forget_thumbnails(&removed);
+    }
+
+    // 删除已经不在历史记录里的图片的缩略图
+    fn forget_thumbnails(&self, removed: &[ClipboardEntry]) {
+        let thumbnails = match &self.thumbnails {
+            Some(x) => x,
+            None => return,
         };
+        for entry in removed {
+            if let manager::ClipboardData::Image(_, hash, _) = &entry.data {
+                // 同一张图片可能还有其他条目在用
+                if !self.datas.iter().any(|e| e.data == entry.data) {
+                    thumbnails.remove(hash);
+                }
+            }
+        }
     }
 
-    pub fn insert_data(&mut self, data: manager::ClipboardData) {
-        if self.datas.is_empty() {
-            self.datas.push_front(data);
-        } else if *self.datas.front().unwrap() != data {
-            // 只有不相同的时候 push
-            self.datas.push_front(data);
+    // 删除满足条件的条目, 返回删除的数量
+    fn remove_where(&mut self, predicate: impl Fn(&ClipboardEntry) -> bool) -> usize {
+        let (removed, kept): (Vec<_>, Vec<_>) = self.datas.drain(..).partition(|e| predicate(e));
+        self.datas = kept.into();
+        if !removed.is_empty() {
+            self.forget_thumbnails(&removed);
+            self.notify_removed(&removed);
+            self.save();
         }
+        return removed.len();
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.datas.iter().map(|e| e.memory_size()).sum()
+    }
+
+    // 是否在单条记录的大小限制以内
+    pub fn accepts(&self, entry: &ClipboardEntry) -> bool {
+        return self.limits.max_entry_bytes == UNLIMITED_SIZE
+            || entry.memory_size() <= self.limits.max_entry_bytes;
+    }
+
+    pub fn stats(&self) -> clipboard::ClipboardStatsResult {
+        return clipboard::ClipboardStatsResult {
+            entry_count: self.datas.len(),
+            pinned_count: self.datas.iter().filter(|e| e.pinned).count(),
+            total_bytes: self.memory_usage(),
+            image_bytes: self
+                .datas
+                .iter()
+                .filter(|e| matches!(e.data, manager::ClipboardData::Image(..)))
+                .map(|e| e.memory_size())
+                .sum(),
+            max_size: self.limits.max_size,
+            max_primary_size: self.limits.max_primary_size,
+            max_entry_bytes: self.limits.max_entry_bytes,
+            max_total_bytes: self.limits.max_total_bytes,
+        };
+    }
 
-        if self.max_size != UNLIMITED_SIZE {
-            // 去掉多余的数据
-            while self.datas.len() > self.max_size {
-                self.datas.pop_back();
+    // 只做标记, 一段时间内的多次修改合并成一次保存
+    fn save(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        if let Some(history) = &self.history {
+            if let Err(err) = history.save(&self.datas, self.next_id) {
+                log::error!("failed to save clipboard history: {:?}", err);
             }
         }
     }
 
+    fn notify(&self, event: clipboard::ClipboardEventKind) {
+        if let Some(entry) = self.datas.front() {
+            // 没有订阅者的时候会返回错误, 忽略即可
+            let _ = self.events.send(clipboard::ClipboardEvent {
+                event,
+                item: entry.to_list_item(),
+            });
+        }
+    }
+
+    fn notify_removed(&self, removed: &[ClipboardEntry]) {
+        for entry in removed {
+            let _ = self.events.send(clipboard::ClipboardEvent {
+                event: clipboard::ClipboardEventKind::Removed,
+                item: entry.to_list_item(),
+            });
+        }
+    }
+
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<clipboard::ClipboardEvent> {
+        return self.events.subscribe();
+    }
+
+    fn attach_thumbnail(&self, entry: &mut ClipboardEntry) {
+        if let (Some(thumbnails), manager::ClipboardData::Image(pixbuf, hash, _)) =
+            (&self.thumbnails, &entry.data)
+        {
+            entry.thumbnail = thumbnails.get_or_create(pixbuf, hash);
+        }
+    }
+
+    // 返回记录下来的条目的 id, 没有记录或者和最新一条相同的时候返回 None
+    pub fn insert_entry(&mut self, mut entry: ClipboardEntry) -> Option<u64> {
+        if self.stopped {
+            return None;
+        }
+        if !self.accepts(&entry) {
+            // 先丢掉额外的格式, 还是太大的话不记录
+            entry.formats.clear();
+            if !self.accepts(&entry) {
+                log::info!(
+                    "ignored clipboard data larger than max_entry_bytes: {} bytes",
+                    entry.memory_size()
+                );
+                return None;
+            }
+        }
+
+        self.attach_thumbnail(&mut entry);
+
+        // 和最新一条相同的时候什么都不做, 恢复剪贴板之类的操作也会走到这里, 不计入使用次数
+        if self.datas.front().is_some_and(|e| e.data == entry.data) {
+            return None;
+        }
+
+        if self.dedupe_history {
+            // 图片按 hash 比较
+            if let Some(idx) = self.datas.iter().position(|e| e.data == entry.data) {
+                let mut existing = self.datas.remove(idx).unwrap();
+                existing.last_used = chrono::Local::now();
+                existing.use_count += 1;
+                // 保留 id, 固定状态和首次出现时间, 其他的以这次复制为准
+                // 复制过的内容之后又被鼠标选中的时候, 仍然算作 CLIPBOARD
+                if existing.selection == clipboard::ClipboardSelection::Primary {
+                    existing.selection = entry.selection;
+                }
+                existing.targets = entry.targets;
+                // 其他格式在后台读取完之后才通过 set_formats 更新, 在那之前先保留原来的
+                if !entry.formats.is_empty() {
+                    existing.formats = entry.formats;
+                }
+                existing.source = entry.source;
+                // 已经会被保存的条目不能因为这次被当成敏感内容而过期删除, 只更新本来就会过期的条目
+                if existing.expires_at.is_some() {
+                    existing.expires_at = entry.expires_at;
+                }
+                let existing_id = existing.id;
+                self.datas.push_front(existing);
+                self.save();
+                self.notify(clipboard::ClipboardEventKind::MovedToFront);
+                return Some(existing_id);
+            }
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        entry.id = id;
+        self.datas.push_front(entry);
+        self.evict();
+        self.save();
+        self.notify(clipboard::ClipboardEventKind::New);
+        return Some(id);
+    }
+
+    // 后台读取完的其他格式, 条目已经被删除的话忽略
+    pub fn set_formats(&mut self, id: u64, formats: Vec<manager::ClipboardFormat>) {
+        let max_entry_bytes = self.limits.max_entry_bytes;
+        let entry = match self.datas.iter_mut().find(|e| e.id == id) {
+            Some(x) => x,
+            None => return,
+        };
+
+        entry.formats = formats;
+        // 加上其他格式之后超出限制的话只保留主要数据
+        if max_entry_bytes != UNLIMITED_SIZE && entry.memory_size() > max_entry_bytes {
+            entry.formats.clear();
+        }
+        self.evict();
+        self.save();
+    }
+
+    // 敏感内容不导出
+    pub fn export(&self) -> anyhow::Result<Vec<u8>> {
+        return archive::export_entries(self.datas.iter().filter(|e| e.expires_at.is_none()));
+    }
+
+    // 导出后停止记录, 历史记录和缩略图交给接手的 clipd, 在同一次加锁里完成, 中间不会漏掉新的内容
+    pub fn export_and_stop(&mut self) -> anyhow::Result<Vec<u8>> {
+        let archive = self.export()?;
+        self.flush();
+        self.stopped = true;
+        self.history = None;
+        self.thumbnails = None;
+        return Ok(archive);
+    }
+
+    // 导入时总是去重, 重复导入同一个归档不会让历史记录翻倍, 返回实际保留下来的条目数
+    pub fn import(&mut self, entries: Vec<ClipboardEntry>) -> usize {
+        let mut imported_ids = HashSet::new();
+        for mut entry in entries {
+            if let Some(existing) = self.datas.iter_mut().find(|e| e.data == entry.data) {
+                existing.pinned |= entry.pinned;
+                continue;
+            }
+            if !self.accepts(&entry) {
+                continue;
+            }
+
+            // id 只在本机有意义, 重新分配
+            entry.id = self.next_id;
+            self.next_id += 1;
+            entry.expires_at = None;
+            self.attach_thumbnail(&mut entry);
+            imported_ids.insert(entry.id);
+            self.datas.push_back(entry);
+        }
+
+        // 最新的一条是当前剪贴板的内容, 保持不动, 其他的按最后使用时间排序
+        if self.datas.len() > 1 {
+            self.datas.make_contiguous()[1..].sort_by_key(|e| std::cmp::Reverse(e.last_used));
+        }
+        self.evict();
+        self.save();
+
+        return self
+            .datas
+            .iter()
+            .filter(|e| imported_ids.contains(&e.id))
+            .count();
+    }
+
+    // 从磁盘读取加密的历史记录, 和锁定期间记录的内容合并, 返回合并进来的条目数
+    pub fn unlock(&mut self, key: file::EncryptionKey) -> anyhow::Result<usize> {
+        let history = match &mut self.history {
+            Some(x) => x,
+            None => return Ok(0),
+        };
+        history.set_key(Some(key));
+        // 已经读取过的话内存里的就是完整的历史记录, 把锁定期间的修改保存下去即可
+        if self.loaded {
+            self.save();
+            return Ok(0);
+        }
+
+        let (entries, saved_next_id) = history.load()?;
+        return Ok(self.merge_loaded(entries, saved_next_id));
+    }
+
+    // 磁盘上的记录保留原来的 id 和顺序, 锁定期间记录的条目排在前面, id 接着磁盘上的重新分配
+    fn merge_loaded(&mut self, mut entries: VecDeque<ClipboardEntry>, saved_next_id: u64) -> usize {
+        for removal in self.pending_removals.drain(..) {
+            entries.retain(|e| !removal.matches(e));
+        }
+        let mut next_id = assign_missing_ids(&mut entries, saved_next_id);
+
+        let mut datas = VecDeque::new();
+        for mut entry in std::mem::take(&mut self.datas) {
+            let existing = if self.dedupe_history {
+                entries
+                    .iter()
+                    .position(|e| e.data == entry.data)
+                    .and_then(|idx| entries.remove(idx))
+            } else {
+                None
+            };
+            match existing {
+                // 和 insert_entry 去重时一样, 保留 id, 固定状态和首次出现时间
+                Some(existing) => {
+                    entry.id = existing.id;
+                    entry.pinned |= existing.pinned;
+                    entry.first_seen = existing.first_seen;
+                    entry.use_count += existing.use_count;
+                }
+                None => {
+                    entry.id = next_id;
+                    next_id += 1;
+                }
+            }
+            datas.push_back(entry);
+        }
+
+        let loaded_ids: HashSet<u64> = entries.iter().map(|e| e.id).collect();
+        for mut entry in entries {
+            self.attach_thumbnail(&mut entry);
+            datas.push_back(entry);
+        }
+        self.datas = datas;
+        self.next_id = next_id;
+        self.loaded = true;
+        self.evict();
+        self.save();
+
+        return self
+            .datas
+            .iter()
+            .filter(|e| loaded_ids.contains(&e.id))
+            .count();
+    }
+
+    // 先把还没保存的修改写下去再丢掉 key, 之后的修改只保存在内存里, 直到再次解锁
+    pub fn lock(&mut self) {
+        self.flush();
+        if let Some(history) = &mut self.history {
+            history.set_key(None);
+        }
+    }
+
+    pub fn remove_expired(&mut self) {
+        let now = chrono::Local::now();
+        self.remove_where(|e| e.expires_at.is_some_and(|expires_at| expires_at <= now));
+    }
+
     pub fn get_latest_data(&self) -> Option<&manager::ClipboardData> {
+        return self.datas.front().map(|e| &e.data);
+    }
+
+    pub fn get_latest_entry(&self) -> Option<&ClipboardEntry> {
         return self.datas.front();
     }
 
-    pub fn list(&self) -> Vec<&manager::ClipboardData> {
+    // 剪贴板的 owner 退出时用来恢复的条目, 只从 CLIPBOARD 的条目里找
+    // 敏感内容由密码管理器自己负责清除, 不能恢复回去
+    pub fn get_restorable_entry(&self) -> Option<&ClipboardEntry> {
+        return self.datas.iter().find(|e| {
+            e.selection == clipboard::ClipboardSelection::Clipboard && e.expires_at.is_none()
+        });
+    }
+
+    pub fn list(&self) -> Vec<&ClipboardEntry> {
         self.datas.iter().collect::<Vec<_>>()
     }
 
-    pub fn move_to_front(&mut self, idx: usize) -> Option<&manager::ClipboardData> {
-        if let Some(data) = self.datas.remove(idx) {
-            self.datas.push_front(data);
-            return self.datas.front();
+    pub fn get(&self, idx: usize) -> Option<&ClipboardEntry> {
+        return self.datas.get(idx);
+    }
+
+    pub fn find_image(&self, hash: &str) -> Option<&manager::ClipboardData> {
+        self.datas
+            .iter()
+            .map(|e| &e.data)
+            .find(|data| matches!(data, manager::ClipboardData::Image(_, x, _) if x == hash))
+    }
+
+    pub fn position(&self, id: u64) -> Option<usize> {
+        self.datas.iter().position(|e| e.id == id)
+    }
+
+    pub fn move_to_front(&mut self, idx: usize) -> Option<&ClipboardEntry> {
+        if let Some(mut entry) = self.datas.remove(idx) {
+            entry.last_used = chrono::Local::now();
+            entry.use_count += 1;
+            self.datas.push_front(entry);
+            self.save();
+            self.notify(clipboard::ClipboardEventKind::MovedToFront);
+            return self.get_latest_entry();
         } else {
             return None;
         }
     }
+
+    pub fn set_pinned(&mut self, idx: usize, pinned: bool) -> bool {
+        if let Some(entry) = self.datas.get_mut(idx) {
+            entry.pinned = pinned;
+            // 取消固定之后可能超出 max_size
+            self.evict();
+            self.save();
+            return true;
+        } else {
+            return false;
+        }
+    }
+
+    pub fn delete(&mut self, idx: usize) -> bool {
+        if let Some(entry) = self.datas.remove(idx) {
+            let removed = [entry];
+            self.forget_thumbnails(&removed);
+            self.notify_removed(&removed);
+            self.save();
+            return true;
+        } else {
+            return false;
+        }
+    }
+
+    pub fn clear(&mut self, keep_pinned: bool) -> usize {
+        return self.remove_persisted(Removal::All { keep_pinned });
+    }
+
+    pub fn delete_matching(&mut self, regex: &regex::Regex, keep_pinned: bool) -> usize {
+        return self.remove_persisted(Removal::Matching {
+            regex: regex.clone(),
+            keep_pinned,
+        });
+    }
+
+    // 按 id 删除只能删掉内存里的条目, 按条件删除的要记下来, 解锁后同样作用到磁盘上的记录
+    fn remove_persisted(&mut self, removal: Removal) -> usize {
+        let removed = self.remove_where(|e| removal.matches(e));
+        if !self.loaded {
+            self.pending_removals.push(removal);
+        }
+        return removed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage(
+        max_size: usize,
+        max_primary_size: usize,
+        max_total_bytes: usize,
+    ) -> ClipboardStorage {
+        let limits = ClipboardLimits {
+            max_size,
+            max_primary_size,
+            max_entry_bytes: UNLIMITED_SIZE,
+            max_total_bytes,
+        };
+        return ClipboardStorage::new(limits, true, None, None);
+    }
+
+    fn text_entry(text: &str, selection: clipboard::ClipboardSelection) -> ClipboardEntry {
+        let mut entry = ClipboardEntry::new(manager::ClipboardData::Text(text.to_string()));
+        entry.selection = selection;
+        return entry;
+    }
+
+    fn texts(storage: &ClipboardStorage) -> Vec<String> {
+        return storage
+            .list()
+            .iter()
+            .map(|e| e.data.to_searchable_text().unwrap_or_default())
+            .collect();
+    }
+
+    #[test]
+    fn evict_oldest_unpinned() {
+        let mut storage = storage(2, 2, UNLIMITED_SIZE);
+        storage.insert_entry(text_entry("a", clipboard::ClipboardSelection::Clipboard));
+        storage.set_pinned(0, true);
+        for text in ["b", "c", "d"] {
+            storage.insert_entry(text_entry(text, clipboard::ClipboardSelection::Clipboard));
+        }
+        assert_eq!(texts(&storage), vec!["d", "c", "a"]);
+    }
+
+    #[test]
+    fn delete_matching_only_saves_when_removed() {
+        let mut storage = storage(10, 10, UNLIMITED_SIZE);
+        storage.insert_entry(text_entry("keep", clipboard::ClipboardSelection::Clipboard));
+        storage.insert_entry(text_entry(
+            "drop 1",
+            clipboard::ClipboardSelection::Clipboard,
+        ));
+        storage.insert_entry(text_entry(
+            "drop 2",
+            clipboard::ClipboardSelection::Clipboard,
+        ));
+        storage.set_pinned(0, true);
+        storage.flush();
+
+        let mut events = storage.subscribe();
+        let regex = regex::Regex::new("^drop").unwrap();
+        assert_eq!(storage.delete_matching(&regex, true), 1);
+        assert_eq!(texts(&storage), vec!["drop 2", "keep"]);
+        assert!(storage.dirty);
+        assert_eq!(
+            events.try_recv().unwrap().event,
+            clipboard::ClipboardEventKind::Removed
+        );
+
+        storage.flush();
+        assert_eq!(storage.delete_matching(&regex, true), 0);
+        assert!(!storage.dirty);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn unlock_keeps_stored_ids_and_removals() {
+        let mut storage = storage(10, 10, UNLIMITED_SIZE);
+        // 模拟启动时历史记录还是锁定的
+        storage.loaded = false;
+        storage.insert_entry(text_entry(
+            "shared",
+            clipboard::ClipboardSelection::Clipboard,
+        ));
+        storage.insert_entry(text_entry("new", clipboard::ClipboardSelection::Clipboard));
+        let regex = regex::Regex::new("^secret").unwrap();
+        assert_eq!(storage.delete_matching(&regex, false), 0);
+
+        let mut stored = VecDeque::new();
+        for (id, text) in [(7, "secret"), (6, "shared"), (5, "old")] {
+            let mut entry = text_entry(text, clipboard::ClipboardSelection::Clipboard);
+            entry.id = id;
+            stored.push_back(entry);
+        }
+        assert_eq!(storage.merge_loaded(stored, 8), 1);
+
+        assert_eq!(texts(&storage), vec!["new", "shared", "old"]);
+        let ids: Vec<u64> = storage.list().iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![8, 6, 5]);
+        assert_eq!(storage.next_id, 9);
+        assert!(storage.pending_removals.is_empty());
+    }
+
+    #[test]
+    fn evict_primary_separately() {
+        let mut storage = storage(2, 1, UNLIMITED_SIZE);
+        storage.insert_entry(text_entry("a", clipboard::ClipboardSelection::Clipboard));
+        storage.insert_entry(text_entry("b", clipboard::ClipboardSelection::Clipboard));
+        storage.insert_entry(text_entry("p1", clipboard::ClipboardSelection::Primary));
+        storage.insert_entry(text_entry("p2", clipboard::ClipboardSelection::Primary));
+        assert_eq!(texts(&storage), vec!["p2", "b", "a"]);
+    }
+
+    #[test]
+    fn dedupe_keeps_persisted_entry() {
+        let mut storage = storage(10, 10, UNLIMITED_SIZE);
+        storage.insert_entry(text_entry("a", clipboard::ClipboardSelection::Clipboard));
+        storage.set_pinned(0, true);
+        storage.insert_entry(text_entry("b", clipboard::ClipboardSelection::Clipboard));
+
+        let mut sensitive = text_entry("a", clipboard::ClipboardSelection::Clipboard);
+        sensitive.expires_at = Some(chrono::Local::now());
+        storage.insert_entry(sensitive);
+        assert_eq!(texts(&storage), vec!["a", "b"]);
+        assert!(storage.get(0).unwrap().expires_at.is_none());
+
+        storage.remove_expired();
+        assert_eq!(texts(&storage), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn evict_by_total_bytes_keeps_latest() {
+        let mut storage = storage(10, 10, 8);
+        storage.insert_entry(text_entry("aaaa", clipboard::ClipboardSelection::Clipboard));
+        storage.insert_entry(text_entry("bbbb", clipboard::ClipboardSelection::Clipboard));
+        storage.insert_entry(text_entry("cccc", clipboard::ClipboardSelection::Clipboard));
+        assert_eq!(texts(&storage), vec!["cccc", "bbbb"]);
+
+        storage.insert_entry(text_entry(
+            "0123456789",
+            clipboard::ClipboardSelection::Clipboard,
+        ));
+        assert_eq!(texts(&storage), vec!["0123456789"]);
+    }
 }
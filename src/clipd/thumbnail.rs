@@ -0,0 +1,71 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+use rofi_toys::file;
+
+// 缩略图最长边的像素
+const THUMBNAIL_SIZE: i32 = 128;
+
+// 给 clipc 显示用的缩略图, 存在 ~/.cache/rofi-toys/clipd/thumbnails/{hash}.png
+#[derive(Debug)]
+pub struct ThumbnailCache {
+    thumbnails_dir: PathBuf,
+}
+
+impl ThumbnailCache {
+    pub fn new() -> anyhow::Result<ThumbnailCache> {
+        let thumbnails_dir = file::get_cache_dir("clipd")?.join("thumbnails");
+        fs::create_dir_all(&thumbnails_dir)?;
+
+        return Ok(ThumbnailCache { thumbnails_dir });
+    }
+
+    pub fn get_or_create(&self, pixbuf: &gtk::gdk_pixbuf::Pixbuf, hash: &str) -> Option<PathBuf> {
+        let thumbnail_path = self.thumbnails_dir.join(format!("{}.png", hash));
+        if thumbnail_path.exists() {
+            return Some(thumbnail_path);
+        }
+
+        // 保持比例缩小, 本身就比较小的图片不放大
+        let (width, height) = (pixbuf.width(), pixbuf.height());
+        let scale = f64::min(
+            1.0,
+            THUMBNAIL_SIZE as f64 / i32::max(width, height).max(1) as f64,
+        );
+        let thumbnail = pixbuf.scale_simple(
+            ((width as f64 * scale) as i32).max(1),
+            ((height as f64 * scale) as i32).max(1),
+            gtk::gdk_pixbuf::InterpType::Bilinear,
+        )?;
+
+        if let Err(err) = thumbnail.savev(&thumbnail_path, "png", &[]) {
+            log::error!("failed to save thumbnail {}: {}", hash, err);
+            return None;
+        }
+        return Some(thumbnail_path);
+    }
+
+    pub fn remove(&self, hash: &str) {
+        let thumbnail_path = self.thumbnails_dir.join(format!("{}.png", hash));
+        match fs::remove_file(&thumbnail_path) {
+            Ok(_) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => log::error!("failed to remove thumbnail {}: {}", hash, err),
+        }
+    }
+
+    // 删除已经不在历史记录里的缩略图
+    pub fn cleanup(&self, hashes: &HashSet<String>) -> anyhow::Result<()> {
+        for entry in fs::read_dir(&self.thumbnails_dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let hash = file_name.strip_suffix(".png").unwrap_or(&file_name);
+            if !hashes.contains(hash) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+}
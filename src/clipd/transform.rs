@@ -0,0 +1,280 @@
+use crate::manager::ClipboardData;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ClipdReplaceRule {
+    pub enabled: bool,
+    pub pattern: String,
+    // 支持 $1 之类的分组引用
+    pub replacement: String,
+}
+
+impl Default for ClipdReplaceRule {
+    fn default() -> Self {
+        ClipdReplaceRule {
+            enabled: true,
+            pattern: String::new(),
+            replacement: String::new(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ClipdTransformConfig {
+    // 去掉首尾的空白
+    pub trim_whitespace: bool,
+    // 去掉每行末尾的空白, 从终端复制的时候经常带上
+    pub trim_trailing_whitespace: bool,
+    // 去掉链接里的跟踪参数
+    pub strip_tracking_params: bool,
+    // 支持末尾 * 通配
+    pub tracking_params: Vec<String>,
+    // 按顺序对文本执行正则替换
+    pub replace_rules: Vec<ClipdReplaceRule>,
+    // 处理后的内容同时设置回剪贴板, 否则只影响历史记录
+    pub update_clipboard: bool,
+}
+
+impl Default for ClipdTransformConfig {
+    fn default() -> Self {
+        ClipdTransformConfig {
+            trim_whitespace: false,
+            trim_trailing_whitespace: false,
+            strip_tracking_params: false,
+            tracking_params: vec![
+                "utm_*".to_string(),
+                "fbclid".to_string(),
+                "gclid".to_string(),
+                "dclid".to_string(),
+                "msclkid".to_string(),
+                "mc_eid".to_string(),
+                "igshid".to_string(),
+                "spm".to_string(),
+            ],
+            replace_rules: Vec::new(),
+            update_clipboard: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TextTransformer {
+    trim_whitespace: bool,
+    trim_trailing_whitespace: bool,
+    strip_tracking_params: bool,
+    tracking_params: Vec<String>,
+    replace_rules: Vec<(regex::Regex, String)>,
+    url_regex: regex::Regex,
+    pub update_clipboard: bool,
+}
+
+impl TextTransformer {
+    pub fn new(config: &ClipdTransformConfig) -> TextTransformer {
+        let replace_rules = config
+            .replace_rules
+            .iter()
+            .filter(|rule| rule.enabled)
+            .filter_map(|rule| match regex::Regex::new(&rule.pattern) {
+                Ok(x) => Some((x, rule.replacement.clone())),
+                Err(err) => {
+                    log::error!("invalid replace pattern {}: {}", rule.pattern, err);
+                    None
+                }
+            })
+            .collect();
+
+        return TextTransformer {
+            trim_whitespace: config.trim_whitespace,
+            trim_trailing_whitespace: config.trim_trailing_whitespace,
+            strip_tracking_params: config.strip_tracking_params,
+            tracking_params: config
+                .tracking_params
+                .iter()
+                .map(|x| x.to_lowercase())
+                .collect(),
+            replace_rules,
+            url_regex: regex::Regex::new(r#"https?://[^\s<>"']+"#).unwrap(),
+            update_clipboard: config.update_clipboard,
+        };
+    }
+
+    fn is_tracking_param(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.tracking_params.iter().any(|x| {
+            if let Some(prefix) = x.strip_suffix('*') {
+                name.starts_with(prefix)
+            } else {
+                name == *x
+            }
+        })
+    }
+
+    fn strip_url(&self, url: &str) -> String {
+        let (base, fragment) = match url.find('#') {
+            Some(idx) => url.split_at(idx),
+            None => (url, ""),
+        };
+        let (path, query) = match base.split_once('?') {
+            Some(x) => x,
+            None => return url.to_string(),
+        };
+
+        // 直接按原文拼接, 不重新编码剩下的参数
+        let params = query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .filter(|param| !self.is_tracking_param(param.split('=').next().unwrap_or_default()))
+            .collect::<Vec<_>>();
+
+        if params.is_empty() {
+            return format!("{}{}", path, fragment);
+        }
+        return format!("{}?{}{}", path, params.join("&"), fragment);
+    }
+
+    fn transform_text(&self, text: &str) -> String {
+        let mut text = text.to_string();
+
+        if self.trim_trailing_whitespace {
+            // 保留原来的换行符 (包括 \r\n) 和末尾的换行
+            text = text
+                .split_inclusive('\n')
+                .map(|line| {
+                    let content = line.trim_end_matches('\n').trim_end_matches('\r');
+                    let ending = &line[content.len()..];
+                    format!("{}{}", content.trim_end(), ending)
+                })
+                .collect::<String>();
+        }
+        if self.trim_whitespace {
+            text = text.trim().to_string();
+        }
+        if self.strip_tracking_params {
+            text = self
+                .url_regex
+                .replace_all(&text, |caps: &regex::Captures| self.strip_url(&caps[0]))
+                .to_string();
+        }
+        for (regex, replacement) in self.replace_rules.iter() {
+            text = regex.replace_all(&text, replacement.as_str()).to_string();
+        }
+
+        return text;
+    }
+
+    // 返回处理后的数据, 没有变化的话返回 None
+    pub fn transform(&self, data: &ClipboardData) -> Option<ClipboardData> {
+        let transformed = match data {
+            ClipboardData::Text(text) => ClipboardData::Text(self.transform_text(text)),
+            ClipboardData::Url(urls) if self.strip_tracking_params => {
+                ClipboardData::Url(urls.iter().map(|x| self.strip_url(x)).collect())
+            }
+            // html 和图片不处理
+            _ => return None,
+        };
+
+        // 处理之后什么都不剩的话保留原样
+        if transformed == *data
+            || transformed
+                .to_searchable_text()
+                .unwrap_or_default()
+                .is_empty()
+        {
+            return None;
+        }
+        return Some(transformed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transformer(config: ClipdTransformConfig) -> TextTransformer {
+        return TextTransformer::new(&config);
+    }
+
+    #[test]
+    fn trim_trailing_whitespace_keeps_line_endings() {
+        let t = transformer(ClipdTransformConfig {
+            trim_trailing_whitespace: true,
+            ..Default::default()
+        });
+        assert_eq!(t.transform_text("a  \nb\t\n"), "a\nb\n");
+        assert_eq!(t.transform_text("a \r\nb \r\n"), "a\r\nb\r\n");
+        assert_eq!(t.transform_text("a \n\n  b"), "a\n\n  b");
+        assert_eq!(t.transform_text("no newline  "), "no newline");
+    }
+
+    #[test]
+    fn strip_url_removes_tracking_params() {
+        let t = transformer(ClipdTransformConfig::default());
+        assert_eq!(
+            t.strip_url("https://example.com/a?utm_source=x&id=1&fbclid=y#top"),
+            "https://example.com/a?id=1#top"
+        );
+        assert_eq!(
+            t.strip_url("https://example.com/a?UTM_Medium=x"),
+            "https://example.com/a"
+        );
+        assert_eq!(
+            t.strip_url("https://example.com/a#b?c"),
+            "https://example.com/a#b?c"
+        );
+        assert_eq!(
+            t.strip_url("https://example.com/a"),
+            "https://example.com/a"
+        );
+    }
+
+    #[test]
+    fn tracking_param_wildcard() {
+        let t = transformer(ClipdTransformConfig::default());
+        assert!(t.is_tracking_param("utm_campaign"));
+        assert!(t.is_tracking_param("GCLID"));
+        assert!(!t.is_tracking_param("utm"));
+        assert!(!t.is_tracking_param("id"));
+    }
+
+    #[test]
+    fn replace_rules_run_in_order() {
+        let t = transformer(ClipdTransformConfig {
+            replace_rules: vec![
+                ClipdReplaceRule {
+                    pattern: "a".to_string(),
+                    replacement: "b".to_string(),
+                    ..Default::default()
+                },
+                ClipdReplaceRule {
+                    pattern: "b".to_string(),
+                    replacement: "c".to_string(),
+                    ..Default::default()
+                },
+                ClipdReplaceRule {
+                    enabled: false,
+                    pattern: "c".to_string(),
+                    replacement: "d".to_string(),
+                },
+            ],
+            ..Default::default()
+        });
+        assert_eq!(t.transform_text("ab"), "cc");
+    }
+
+    #[test]
+    fn transform_keeps_original_when_empty() {
+        let t = transformer(ClipdTransformConfig {
+            trim_whitespace: true,
+            ..Default::default()
+        });
+        assert!(t
+            .transform(&ClipboardData::Text("   ".to_string()))
+            .is_none());
+        assert!(t.transform(&ClipboardData::Text("a".to_string())).is_none());
+        assert_eq!(
+            t.transform(&ClipboardData::Text(" a ".to_string())),
+            Some(ClipboardData::Text("a".to_string()))
+        );
+    }
+}
@@ -0,0 +1,495 @@
+use rofi_toys::clipboard;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::rc::Rc;
+use wayland_client::backend::ObjectId;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_registry, wl_seat};
+use wayland_client::{Connection, Dispatch, EventQueue, Proxy, QueueHandle};
+use wayland_protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_device_v1, zwlr_data_control_manager_v1, zwlr_data_control_offer_v1,
+    zwlr_data_control_source_v1,
+};
+
+use crate::backend::{self, ClipboardBackend};
+use crate::manager::{ClipboardData, ClipboardFormat};
+
+// 读对方数据的超时, 避免对方不响应的时候卡住主循环
+const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+struct WlrOffer {
+    offer: zwlr_data_control_offer_v1::ZwlrDataControlOfferV1,
+    mime_types: Vec<String>,
+}
+
+struct WlrState {
+    manager: zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+    device: zwlr_data_control_device_v1::ZwlrDataControlDeviceV1,
+    // 还没有成为 selection 的 offer 的 mime type
+    pending_offers: HashMap<ObjectId, Vec<String>>,
+    // 下标见 selection_index
+    selections: [Option<WlrOffer>; 2],
+    // 自己提供的 source, 对方请求的时候从这里取数据
+    sources: HashMap<ObjectId, Vec<ClipboardFormat>>,
+    // 自己刚设置了 selection, 下一次 selection 事件是自己引起的
+    self_offered: [bool; 2],
+    changed: Vec<clipboard::ClipboardSelection>,
+}
+
+struct WlrInner {
+    conn: Connection,
+    queue: EventQueue<WlrState>,
+    state: WlrState,
+    callbacks: Vec<(clipboard::ClipboardSelection, Rc<dyn Fn()>)>,
+}
+
+// wlr-data-control 协议, 不需要窗口和焦点就能读写 wayland 剪贴板
+// CLIPBOARD 和 PRIMARY 共用一个 wayland 连接
+pub struct WlrBackend {
+    inner: Rc<RefCell<WlrInner>>,
+    selection: clipboard::ClipboardSelection,
+}
+
+fn selection_index(selection: clipboard::ClipboardSelection) -> usize {
+    match selection {
+        clipboard::ClipboardSelection::Clipboard => 0,
+        clipboard::ClipboardSelection::Primary => 1,
+    }
+}
+
+impl WlrState {
+    fn set_selection(
+        &mut self,
+        selection: clipboard::ClipboardSelection,
+        offer: Option<zwlr_data_control_offer_v1::ZwlrDataControlOfferV1>,
+    ) {
+        let idx = selection_index(selection);
+        if let Some(old) = self.selections[idx].take() {
+            old.offer.destroy();
+        }
+        self.selections[idx] = offer.map(|offer| WlrOffer {
+            mime_types: self.pending_offers.remove(&offer.id()).unwrap_or_default(),
+            offer,
+        });
+
+        if self.self_offered[idx] {
+            self.self_offered[idx] = false;
+        } else {
+            self.changed.push(selection);
+        }
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for WlrState {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<zwlr_data_control_device_v1::ZwlrDataControlDeviceV1, ()> for WlrState {
+    fn event(
+        state: &mut Self,
+        _: &zwlr_data_control_device_v1::ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_device_v1::Event::DataOffer { id } => {
+                state.pending_offers.insert(id.id(), Vec::new());
+            }
+            zwlr_data_control_device_v1::Event::Selection { id } => {
+                state.set_selection(clipboard::ClipboardSelection::Clipboard, id);
+            }
+            zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
+                state.set_selection(clipboard::ClipboardSelection::Primary, id);
+            }
+            zwlr_data_control_device_v1::Event::Finished => {
+                log::error!("wlr data control device finished");
+            }
+            _ => {}
+        }
+    }
+
+    wayland_client::event_created_child!(WlrState, zwlr_data_control_device_v1::ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (zwlr_data_control_offer_v1::ZwlrDataControlOfferV1, ()),
+    ]);
+}
+
+impl Dispatch<zwlr_data_control_offer_v1::ZwlrDataControlOfferV1, ()> for WlrState {
+    fn event(
+        state: &mut Self,
+        offer: &zwlr_data_control_offer_v1::ZwlrDataControlOfferV1,
+        event: zwlr_data_control_offer_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
+            if let Some(mime_types) = state.pending_offers.get_mut(&offer.id()) {
+                mime_types.push(mime_type);
+            }
+        }
+    }
+}
+
+impl Dispatch<zwlr_data_control_source_v1::ZwlrDataControlSourceV1, ()> for WlrState {
+    fn event(
+        state: &mut Self,
+        source: &zwlr_data_control_source_v1::ZwlrDataControlSourceV1,
+        event: zwlr_data_control_source_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_source_v1::Event::Send { mime_type, fd } => {
+                let data = state.sources.get(&source.id()).and_then(|formats| {
+                    formats
+                        .iter()
+                        .find(|x| x.target == mime_type)
+                        .map(|x| x.data.clone())
+                });
+                if let Some(data) = data {
+                    // 对方读得慢的话会阻塞, 放到单独的线程里写
+                    std::thread::spawn(move || {
+                        let mut file = std::fs::File::from(fd);
+                        if let Err(err) = file.write_all(&data) {
+                            log::error!("failed to send {}: {}", mime_type, err);
+                        }
+                    });
+                }
+            }
+            zwlr_data_control_source_v1::Event::Cancelled => {
+                state.sources.remove(&source.id());
+                source.destroy();
+            }
+            _ => {}
+        }
+    }
+}
+
+wayland_client::delegate_noop!(WlrState: ignore wl_seat::WlSeat);
+wayland_client::delegate_noop!(WlrState: zwlr_data_control_manager_v1::ZwlrDataControlManagerV1);
+
+// 把 data 转换成要提供的所有 mime type, 原样保存的 formats 只补充 data 提供不了的
+// 来源提供了纯文本的话用原样保存的, 不从 data 转换
+fn offer_formats(data: ClipboardData, formats: Vec<ClipboardFormat>) -> Vec<ClipboardFormat> {
+    let mut defaults = Vec::new();
+    match &data {
+        ClipboardData::Url(urls) => {
+            defaults.push(("text/uri-list", urls.join("\r\n").into_bytes()));
+        }
+        ClipboardData::Html(html) => {
+            defaults.push(("text/html", html.as_bytes().to_vec()));
+        }
+        ClipboardData::Image(image, _, _) => match image.save_to_bufferv("png", &[]) {
+            Ok(png) => defaults.push(("image/png", png)),
+            Err(err) => log::error!("failed to encode image: {}", err),
+        },
+        ClipboardData::Binary(mime, bytes) => {
+            defaults.push((mime.as_str(), bytes.clone()));
+        }
+        ClipboardData::Text(_) => {}
+    }
+    if let Some(text) = data
+        .to_searchable_text()
+        .filter(|_| backend::needs_text_targets(&data, &formats))
+    {
+        for target in [
+            "text/plain;charset=utf-8",
+            "text/plain",
+            "UTF8_STRING",
+            "STRING",
+            "TEXT",
+        ] {
+            defaults.push((target, text.as_bytes().to_vec()));
+        }
+    }
+
+    let mut result = defaults
+        .into_iter()
+        .map(|(target, data)| ClipboardFormat {
+            target: target.to_string(),
+            data,
+        })
+        .collect::<Vec<_>>();
+    for format in formats {
+        if !result.iter().any(|x| x.target == format.target) {
+            result.push(format);
+        }
+    }
+    return result;
+}
+
+fn read_pipe(fd: OwnedFd) -> Option<Vec<u8>> {
+    let timeout = rustix::event::Timespec {
+        tv_sec: READ_TIMEOUT.as_secs() as i64,
+        tv_nsec: 0,
+    };
+    let mut file = std::fs::File::from(fd);
+    let mut data = Vec::new();
+    let mut buf = [0u8; 8192];
+
+    loop {
+        let mut fds = [rustix::event::PollFd::new(
+            &file,
+            rustix::event::PollFlags::IN,
+        )];
+        match rustix::event::poll(&mut fds, Some(&timeout)) {
+            Ok(0) => {
+                log::error!("timeout while reading clipboard data");
+                return None;
+            }
+            Ok(_) => {}
+            Err(rustix::io::Errno::INTR) => continue,
+            Err(err) => {
+                log::error!("failed to poll clipboard pipe: {}", err);
+                return None;
+            }
+        }
+
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                log::error!("failed to read clipboard pipe: {}", err);
+                return None;
+            }
+        }
+    }
+
+    return Some(data);
+}
+
+impl WlrBackend {
+    // 让 selection 的 owner 把 target 写到管道里, 返回读的一端
+    fn receive(&self, target: &str) -> Option<OwnedFd> {
+        let inner = self.inner.borrow();
+        let offer = inner.state.selections[selection_index(self.selection)].as_ref()?;
+        if !offer.mime_types.iter().any(|x| x == target) {
+            return None;
+        }
+
+        let (read_fd, write_fd) = rustix::pipe::pipe_with(rustix::pipe::PipeFlags::CLOEXEC)
+            .map_err(|err| log::error!("failed to create pipe: {}", err))
+            .ok()?;
+        offer.offer.receive(target.to_string(), write_fd.as_fd());
+        // 自己这边的写端要关掉, 不然读不到 EOF
+        drop(write_fd);
+        if let Err(err) = inner.conn.flush() {
+            log::error!("failed to flush wayland connection: {}", err);
+            return None;
+        }
+        return Some(read_fd);
+    }
+
+    // 返回 (CLIPBOARD, PRIMARY)
+    pub fn new() -> anyhow::Result<(WlrBackend, WlrBackend)> {
+        let conn = Connection::connect_to_env()?;
+        let (globals, mut queue) = registry_queue_init::<WlrState>(&conn)?;
+        let qh = queue.handle();
+
+        let seat: wl_seat::WlSeat = globals.bind(&qh, 1..=1, ())?;
+        // version 2 才支持 PRIMARY
+        let manager: zwlr_data_control_manager_v1::ZwlrDataControlManagerV1 =
+            globals.bind(&qh, 1..=2, ())?;
+        let device = manager.get_data_device(&seat, &qh, ());
+
+        let mut state = WlrState {
+            manager,
+            device,
+            pending_offers: HashMap::new(),
+            selections: [None, None],
+            sources: HashMap::new(),
+            self_offered: [false, false],
+            changed: Vec::new(),
+        };
+        queue.roundtrip(&mut state)?;
+        // 启动时已有的内容不算变化, 和 gtk backend 保持一致
+        state.changed.clear();
+
+        let fd = conn.backend().poll_fd().as_raw_fd();
+        let inner = Rc::new(RefCell::new(WlrInner {
+            conn,
+            queue,
+            state,
+            callbacks: Vec::new(),
+        }));
+
+        // 在 glib 主循环里处理 wayland 事件
+        let dispatch_inner = inner.clone();
+        gtk::glib::source::unix_fd_add_local(fd, gtk::glib::IOCondition::IN, move |_, _| {
+            return Self::dispatch(&dispatch_inner);
+        });
+
+        return Ok((
+            WlrBackend {
+                inner: inner.clone(),
+                selection: clipboard::ClipboardSelection::Clipboard,
+            },
+            WlrBackend {
+                inner,
+                selection: clipboard::ClipboardSelection::Primary,
+            },
+        ));
+    }
+
+    fn dispatch(inner: &Rc<RefCell<WlrInner>>) -> gtk::glib::ControlFlow {
+        let (changed, callbacks) = {
+            let mut inner = inner.borrow_mut();
+            let WlrInner {
+                queue,
+                state,
+                callbacks,
+                ..
+            } = &mut *inner;
+
+            if let Some(guard) = queue.prepare_read() {
+                if let Err(err) = guard.read() {
+                    if !matches!(&err, wayland_client::backend::WaylandError::Io(io) if io.kind() == std::io::ErrorKind::WouldBlock)
+                    {
+                        log::error!("wayland connection error: {}", err);
+                        return gtk::glib::ControlFlow::Break;
+                    }
+                }
+            }
+            if let Err(err) = queue.dispatch_pending(state) {
+                log::error!("failed to dispatch wayland events: {}", err);
+                return gtk::glib::ControlFlow::Break;
+            }
+            let _ = queue.flush();
+
+            (std::mem::take(&mut state.changed), callbacks.clone())
+        };
+
+        // 回调里会再次借用 inner, 所以要在 borrow 释放之后调用
+        for selection in changed {
+            for (_, callback) in callbacks.iter().filter(|(x, _)| *x == selection) {
+                callback();
+            }
+        }
+        return gtk::glib::ControlFlow::Continue;
+    }
+}
+
+impl ClipboardBackend for WlrBackend {
+    fn connect_owner_change(&self, callback: Box<dyn Fn()>) {
+        self.inner
+            .borrow_mut()
+            .callbacks
+            .push((self.selection, Rc::from(callback)));
+    }
+
+    fn targets(&self) -> Option<Vec<String>> {
+        let inner = self.inner.borrow();
+        inner.state.selections[selection_index(self.selection)]
+            .as_ref()
+            .map(|x| x.mime_types.clone())
+    }
+
+    fn read(&self, target: &str) -> Option<Vec<u8>> {
+        return read_pipe(self.receive(target)?);
+    }
+
+    fn read_async(&self, target: &str, callback: Box<dyn FnOnce(Option<Vec<u8>>)>) {
+        let read_fd = match self.receive(target) {
+            Some(x) => x,
+            None => return callback(None),
+        };
+        if let Err(err) = rustix::fs::fcntl_setfl(&read_fd, rustix::fs::OFlags::NONBLOCK) {
+            log::error!("failed to set pipe nonblocking: {}", err);
+            return callback(None);
+        }
+
+        // 读完, 出错或者超时的时候取出 callback, 保证只调用一次
+        let pending = Rc::new(RefCell::new(Some(callback)));
+        let timeout: Rc<RefCell<Option<gtk::glib::SourceId>>> = Rc::new(RefCell::new(None));
+
+        let mut file = std::fs::File::from(read_fd);
+        let mut data = Vec::new();
+        let watch_pending = pending.clone();
+        let watch_timeout = timeout.clone();
+        let watch = gtk::glib::source::unix_fd_add_local(
+            file.as_raw_fd(),
+            gtk::glib::IOCondition::IN | gtk::glib::IOCondition::HUP | gtk::glib::IOCondition::ERR,
+            move |_, _| {
+                let mut buf = [0u8; 8192];
+                let finished = loop {
+                    match file.read(&mut buf) {
+                        Ok(0) => break true,
+                        Ok(n) => data.extend_from_slice(&buf[..n]),
+                        Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                        // 暂时读完了, 等下一次可读
+                        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                            return gtk::glib::ControlFlow::Continue;
+                        }
+                        Err(err) => {
+                            log::error!("failed to read clipboard pipe: {}", err);
+                            break false;
+                        }
+                    }
+                };
+
+                if let Some(source) = watch_timeout.borrow_mut().take() {
+                    source.remove();
+                }
+                let callback = watch_pending.borrow_mut().take();
+                if let Some(callback) = callback {
+                    callback(finished.then(|| std::mem::take(&mut data)));
+                }
+                return gtk::glib::ControlFlow::Break;
+            },
+        );
+
+        *timeout.borrow_mut() = Some(gtk::glib::timeout_add_local_once(READ_TIMEOUT, move || {
+            log::error!("timeout while reading clipboard data");
+            watch.remove();
+            let callback = pending.borrow_mut().take();
+            if let Some(callback) = callback {
+                callback(None);
+            }
+        }));
+    }
+
+    fn offer(&self, data: ClipboardData, formats: Vec<ClipboardFormat>) {
+        let formats = offer_formats(data, formats);
+
+        let mut inner = self.inner.borrow_mut();
+        let WlrInner {
+            conn, queue, state, ..
+        } = &mut *inner;
+
+        if self.selection == clipboard::ClipboardSelection::Primary && state.manager.version() < 2 {
+            log::error!("compositor does not support primary selection");
+            return;
+        }
+
+        let source = state.manager.create_data_source(&queue.handle(), ());
+        for format in formats.iter() {
+            source.offer(format.target.clone());
+        }
+        match self.selection {
+            clipboard::ClipboardSelection::Clipboard => state.device.set_selection(Some(&source)),
+            clipboard::ClipboardSelection::Primary => {
+                state.device.set_primary_selection(Some(&source))
+            }
+        }
+        state.self_offered[selection_index(self.selection)] = true;
+        state.sources.insert(source.id(), formats);
+
+        if let Err(err) = conn.flush() {
+            log::error!("failed to flush wayland connection: {}", err);
+        }
+    }
+}
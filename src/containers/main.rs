@@ -348,7 +348,7 @@ pub fn container_menu(rofi: &RofiPlugin, params: Vec<String>) -> anyhow::Result<
 }
 
 pub fn copy_to_clipboard(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
-    clipboard::clipboard_set_text(&params[0]);
+    clipboard::ClipdClient::new().set_text(&params[0])?;
 
     Ok(())
 }
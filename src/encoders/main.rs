@@ -38,67 +38,100 @@ fn get_string_length(str: &str) -> usize {
     str.chars().count()
 }
 
+// 按 magic number 猜测二进制数据的类型, 这样解码出来的图片可以直接粘贴
+fn sniff_mime(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        "image/jpeg"
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        "image/gif"
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        "image/webp"
+    } else if data.starts_with(b"%PDF-") {
+        "application/pdf"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+// 解码结果是合法的 utf-8 就当作文本, 否则按原始数据设置
+fn set_decoded_bytes(client: &clipboard::ClipdClient, data: Vec<u8>) -> anyhow::Result<()> {
+    match String::from_utf8(data) {
+        Ok(text) => client.set_text(&text)?,
+        Err(err) => {
+            let data = err.into_bytes();
+            client.set(&data, sniff_mime(&data))?
+        }
+    }
+
+    Ok(())
+}
+
 fn len(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
-    clipboard::clipboard_set_text(&get_string_length(&input).to_string());
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
+    client.set_text(&get_string_length(&input).to_string())?;
 
     Ok(())
 }
 
 fn base64_encoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
-    clipboard::clipboard_set_text(&base64_engine::STANDARD.encode(&input));
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest()?;
+    client.set_text(&base64_engine::STANDARD.encode(&input.data))?;
 
     Ok(())
 }
 
 fn base64_decoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let decode_result: Vec<u8> = base64_engine::STANDARD.decode(&input)?;
-    clipboard::clipboard_set_text(&String::from_utf8_lossy(&decode_result).to_string());
-
-    Ok(())
+    set_decoded_bytes(&client, decode_result)
 }
 
 fn base64_url_encoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
-    clipboard::clipboard_set_text(&base64_engine::URL_SAFE.encode(&input));
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest()?;
+    client.set_text(&base64_engine::URL_SAFE.encode(&input.data))?;
 
     Ok(())
 }
 
 fn base64_url_decoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let decode_result = base64_engine::URL_SAFE.decode(&input)?;
-    clipboard::clipboard_set_text(&String::from_utf8_lossy(&decode_result).to_string());
-
-    Ok(())
+    set_decoded_bytes(&client, decode_result)
 }
 
 fn hex_encoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
-    clipboard::clipboard_set_text(&hex::encode(input.as_bytes()));
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest()?;
+    client.set_text(&hex::encode(&input.data))?;
 
     Ok(())
 }
 
 fn hex_decoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let decode_result = hex::decode(&input)?;
-    clipboard::clipboard_set_text(&String::from_utf8_lossy(&decode_result).to_string());
-
-    Ok(())
+    set_decoded_bytes(&client, decode_result)
 }
 
 fn url_encoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
-    clipboard::clipboard_set_text(&urlencoding::encode(&input));
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
+    client.set_text(&urlencoding::encode(&input))?;
 
     Ok(())
 }
 
 fn url_all_encoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let encode_result: String = input
         .as_bytes()
         .iter()
@@ -106,35 +139,39 @@ fn url_all_encoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
             acc.push_str(percent_encoding::percent_encode_byte(*curr));
             acc
         });
-    clipboard::clipboard_set_text(&encode_result);
+    client.set_text(&encode_result)?;
 
     Ok(())
 }
 
 fn url_decoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let decode_result = urlencoding::decode(&input)?;
-    clipboard::clipboard_set_text(&decode_result);
+    client.set_text(&decode_result)?;
 
     Ok(())
 }
 
 fn html_encoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
-    clipboard::clipboard_set_text(&html_escape::encode_unquoted_attribute(&input));
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
+    client.set_text(&html_escape::encode_unquoted_attribute(&input))?;
 
     Ok(())
 }
 
 fn html_decoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
-    clipboard::clipboard_set_text(&html_escape::decode_html_entities(&input));
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
+    client.set_text(&html_escape::decode_html_entities(&input))?;
 
     Ok(())
 }
 
 fn unicode_encoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let encode_result = input.chars().fold(String::new(), |mut acc, c| {
         let c32 = c as u32;
         if c32 < 65536 {
@@ -146,7 +183,7 @@ fn unicode_encoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
         }
         acc
     });
-    clipboard::clipboard_set_text(&encode_result);
+    client.set_text(&encode_result)?;
 
     Ok(())
 }
@@ -222,9 +259,10 @@ fn unicode_decodeing_helper(input: String) -> Option<String> {
 }
 
 fn unicode_decoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     if let Some(decode_result) = unicode_decodeing_helper(input) {
-        clipboard::clipboard_set_text(&decode_result);
+        client.set_text(&decode_result)?;
         Ok(())
     } else {
         Err(RofiPluginError::new("unicode decoding failed").into())
@@ -232,16 +270,18 @@ fn unicode_decoding(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
 }
 
 fn pyeval(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
 
     let result = Python::with_gil(|py| py.eval(&input, None, None).map(|v| v.to_string()));
-    clipboard::clipboard_set_text(&result?);
+    client.set_text(&result?)?;
 
     Ok(())
 }
 
 fn pyeval_input(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
 
     let result = Python::with_gil(|py| {
         let locals = pyo3::types::PyDict::new(py);
@@ -250,13 +290,14 @@ fn pyeval_input(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
         py.eval(&params[0], None, Some(locals))
             .map(|v| v.to_string())
     });
-    clipboard::clipboard_set_text(&result?);
+    client.set_text(&result?)?;
 
     Ok(())
 }
 
 fn pyexec(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
 
     let result = Python::with_gil(|py| {
         let locals = pyo3::types::PyDict::new(py);
@@ -282,7 +323,7 @@ __output = __stdout.getvalue()
     result.0?;
 
     if let Some(result) = result.1 {
-        clipboard::clipboard_set_text(&result);
+        client.set_text(&result)?;
 
         Ok(())
     } else {
@@ -291,42 +332,48 @@ __output = __stdout.getvalue()
 }
 
 fn replace(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
-    clipboard::clipboard_set_text(&input.replace(&params[0], &params[1]));
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
+    client.set_text(&input.replace(&params[0], &params[1]))?;
 
     Ok(())
 }
 
 fn regex_replace(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let regex = regex::Regex::new(&params[0])?;
-    clipboard::clipboard_set_text(&regex.replace_all(&input, &params[1]));
+    client.set_text(&regex.replace_all(&input, &params[1]))?;
 
     Ok(())
 }
 
 fn remove(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
-    clipboard::clipboard_set_text(&input.replace(&params[0], ""));
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
+    client.set_text(&input.replace(&params[0], ""))?;
 
     Ok(())
 }
 
 fn regex_remove(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let regex = regex::Regex::new(&params[0])?;
-    clipboard::clipboard_set_text(&regex.replace_all(&input, ""));
+    client.set_text(&regex.replace_all(&input, ""))?;
 
     Ok(())
 }
 
 fn uuid(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    clipboard::clipboard_set_text(&Uuid::new_v4().to_string());
+    let client = clipboard::ClipdClient::new();
+    client.set_text(&Uuid::new_v4().to_string())?;
 
     Ok(())
 }
 
 fn random(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    let client = clipboard::ClipdClient::new();
     let length = params[0].parse::<usize>()?;
     let charset = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789".as_bytes();
     let mut rng = rand::thread_rng();
@@ -335,67 +382,73 @@ fn random(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
     for _ in 0..length {
         result.push(charset[rng.gen_range(0..charset.len())]);
     }
-    clipboard::clipboard_set_text(&String::from_utf8_lossy(result.as_slice()));
+    client.set_text(&String::from_utf8_lossy(result.as_slice()))?;
 
     Ok(())
 }
 
 fn json_format(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let value = serde_json::from_str::<serde_json::Value>(&input)?;
-    clipboard::clipboard_set_text(&serde_json::to_string_pretty(&value).unwrap());
+    client.set_text(&serde_json::to_string_pretty(&value).unwrap())?;
 
     Ok(())
 }
 
 fn md5(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
 
     let mut hasher = Md5::new();
     hasher.update(input.as_bytes());
 
     let hash = hasher.finalize();
-    clipboard::clipboard_set_text(&hex::encode(&hash));
+    client.set_text(&hex::encode(&hash))?;
 
     Ok(())
 }
 
 fn sha256(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
 
     let mut hasher = Sha256::new();
     hasher.update(input.as_bytes());
 
     let hash = hasher.finalize();
-    clipboard::clipboard_set_text(&hex::encode(&hash));
+    client.set_text(&hex::encode(&hash))?;
 
     Ok(())
 }
 
 fn upper(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
-    clipboard::clipboard_set_text(&input.to_uppercase());
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
+    client.set_text(&input.to_uppercase())?;
 
     Ok(())
 }
 
 fn lower(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
-    clipboard::clipboard_set_text(&input.to_lowercase());
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
+    client.set_text(&input.to_lowercase())?;
 
     Ok(())
 }
 
 fn substring(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    let client = clipboard::ClipdClient::new();
     let (start, mut end) = (params[0].parse::<usize>()?, params[1].parse::<usize>()?);
-    let input = clipboard::clipboard_get_text();
+    let input = client.get_latest_text()?;
     let input_length = input.len();
     if start < input_length && start < end {
         if end > input_length {
             end = input_length;
         }
 
-        clipboard::clipboard_set_text(&input[start..end]);
+        client.set_text(&input[start..end])?;
         Ok(())
     } else {
         Err(RofiPluginError::new("invalid start/end").into())
@@ -404,7 +457,8 @@ fn substring(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
 
 fn baidu_translate(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
     let encoder_config = read_config();
-    let text = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let text = client.get_latest_text()?;
     if encoder_config.baidu_fanyi_appid.is_empty() || encoder_config.baidu_fanyi_secret.is_empty() {
         return Err(RofiPluginError::new("set baidu fanyi appid and secret first").into());
     }
@@ -464,7 +518,7 @@ fn baidu_translate(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
     }
     let response_data: TranslateResponse = serde_json::from_str(response.text()?.as_str())?;
     if response_data.trans_result.is_some() {
-        clipboard::clipboard_set_text(response_data.trans_result.unwrap()[0].get("dst").unwrap());
+        client.set_text(response_data.trans_result.unwrap()[0].get("dst").unwrap())?;
         Ok(())
     } else {
         let error_msg = response_data.error_msg.unwrap();
@@ -492,7 +546,8 @@ impl serde::Serialize for DuplicateQSValue {
 }
 
 fn qs_to_json(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let mut qs_value: HashMap<String, DuplicateQSValue> = HashMap::new();
 
     for kv in form_urlencoded::parse(input.as_bytes()) {
@@ -502,15 +557,16 @@ fn qs_to_json(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
             .push(kv.1.to_string());
     }
 
-    clipboard::clipboard_set_text(&serde_json::to_string(&qs_value).unwrap());
+    client.set_text(&serde_json::to_string(&qs_value).unwrap())?;
 
     Ok(())
 }
 
 fn ord(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     if let Some(c) = input.chars().next() {
-        clipboard::clipboard_set_text(&(c as u32).to_string());
+        client.set_text(&(c as u32).to_string())?;
         Ok(())
     } else {
         Err(RofiPluginError::new("invalid input for ord").into())
@@ -518,12 +574,13 @@ fn ord(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
 }
 
 fn chr(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let codepoint = input.parse::<usize>()?;
     if let Some(c) = char::from_u32(codepoint as u32) {
         let mut s = String::new();
         s.push(c);
-        clipboard::clipboard_set_text(&s);
+        client.set_text(&s)?;
 
         Ok(())
     } else {
@@ -532,7 +589,8 @@ fn chr(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
 }
 
 fn entrypoint(rofi: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let input = clipboard::clipboard_get_text();
+    let client = clipboard::ClipdClient::new();
+    let input = client.get_latest_text()?;
     let input_length = get_string_length(&input);
 
     let mut input = input.chars().take(100).collect::<String>();
@@ -1,36 +1,53 @@
 use http::request::Builder;
 use isahc::{config::Dialer, prelude::*};
 use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::os::unix::fs::MetadataExt;
+
+use crate::file;
 
 pub const CLIPBOARD_LIST: &str = "/list";
 pub const CLIPBOARD_SET_BY_IDX: &str = "/set_by_idx";
+pub const CLIPBOARD_SET_BY_ID: &str = "/set_by_id";
+pub const CLIPBOARD_PIN_BY_IDX: &str = "/pin_by_idx";
+pub const CLIPBOARD_PIN_BY_ID: &str = "/pin_by_id";
+pub const CLIPBOARD_UNPIN_BY_IDX: &str = "/unpin_by_idx";
+pub const CLIPBOARD_UNPIN_BY_ID: &str = "/unpin_by_id";
+pub const CLIPBOARD_SEARCH: &str = "/search";
+pub const CLIPBOARD_DELETE_BY_IDX: &str = "/delete_by_idx";
+pub const CLIPBOARD_DELETE_BY_ID: &str = "/delete_by_id";
+pub const CLIPBOARD_CLEAR: &str = "/clear";
+pub const CLIPBOARD_DELETE_MATCHING: &str = "/delete_matching";
+pub const CLIPBOARD_SUBSCRIBE: &str = "/subscribe";
+pub const CLIPBOARD_GET_BY_IDX: &str = "/get_by_idx";
+pub const CLIPBOARD_GET_BY_ID: &str = "/get_by_id";
+pub const CLIPBOARD_STATS: &str = "/stats";
+// tar 归档, manifest.json 加上 images/{hash}.png
+pub const CLIPBOARD_EXPORT: &str = "/export";
+pub const CLIPBOARD_IMPORT: &str = "/import";
+// 通知 clipd 使用缓存的 key 读写加密的历史记录
+pub const CLIPBOARD_UNLOCK: &str = "/unlock";
+pub const CLIPBOARD_LOCK: &str = "/lock";
+pub const CLIPBOARD_HEALTH: &str = "/health";
+// clipd --replace 用来让正在运行的 clipd 退出
+pub const CLIPBOARD_SHUTDOWN: &str = "/shutdown";
+// GET /image/{hash}.png
+pub const CLIPBOARD_IMAGE: &str = "/image";
 
 pub const CLIPBOARD_GET_LATEST_TEXT: &str = "/get_latest_text";
 pub const CLIPBOARD_SET_TEXT: &str = "/set_text";
+// 原始数据, 类型通过 Content-Type 传递
+pub const CLIPBOARD_GET_LATEST: &str = "/get_latest";
+pub const CLIPBOARD_SET: &str = "/set";
+pub const CLIPBOARD_SET_IMAGE: &str = "/set_image";
+pub const CLIPBOARD_SET_URIS: &str = "/set_uris";
 
-fn get_tmp_dir() -> String {
-    // 先尝试 XDG_RUNTIME_DIR (/run/user/1000), 不行用 /tmp/
-    if let Ok(tmp_dir) = std::env::var("XDG_RUNTIME_DIR") {
-        let md = std::fs::metadata(&tmp_dir);
-        if let Ok(md) = md {
-            let permissions = md.permissions();
-            if !permissions.readonly() {
-                return tmp_dir;
-            }
-        }
-    }
-
-    return std::env::temp_dir().to_string_lossy().to_string();
-}
+// 接口有不兼容的修改时加一, clipd 在每个响应里带上这个 header
+pub const CLIPD_API_VERSION: u32 = 1;
+pub const CLIPD_API_VERSION_HEADER: &str = "x-clipd-api-version";
 
 pub fn get_clipd_listen_path() -> String {
-    return format!("{}/clipd.sock", get_tmp_dir());
-}
-
-pub fn get_clipd_request(path: &str) -> Builder {
-    Builder::new()
-        .uri(format!("http://localhost{}", path))
-        .dial(Dialer::unix_socket(get_clipd_listen_path()))
+    return format!("{}/clipd.sock", file::get_tmp_dir());
 }
 
 #[derive(Serialize, Deserialize)]
@@ -43,69 +60,837 @@ pub struct ClipboardSetTextRequest {
     pub text: String,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardSetUrisRequest {
+    // file:// uri, 也可以直接传绝对路径
+    pub uris: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardEntryKind {
+    Text,
+    Url,
+    Html,
+    Image,
+    Binary,
+}
+
+impl std::fmt::Display for ClipboardEntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = match self {
+            Self::Text => "text",
+            Self::Url => "url",
+            Self::Html => "html",
+            Self::Image => "image",
+            Self::Binary => "binary",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+// 条目是从哪个 selection 复制来的
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardSelection {
+    #[default]
+    Clipboard,
+    Primary,
+}
+
+// 恢复条目时写入哪个 selection
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardSetTarget {
+    #[default]
+    Clipboard,
+    Primary,
+    Both,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ClipboardSource {
+    // WM_CLASS 的两部分, 比如 org.wezfurlong.wezterm
+    pub class: String,
+    pub instance: String,
+    // 复制时的窗口标题
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClipboardListItem {
+    pub id: u64,
+    pub kind: ClipboardEntryKind,
+    pub selection: ClipboardSelection,
+    pub pinned: bool,
+    pub byte_length: usize,
+    pub char_count: usize,
+    // unix timestamp
+    pub first_seen: i64,
+    pub last_used: i64,
+    // 被复制或者设置的次数
+    #[serde(default)]
+    pub use_count: u32,
+    // 复制时剪贴板提供的 MIME target
+    pub targets: Vec<String>,
+    // 最多 512 个字符
+    pub preview: String,
+    // 图片缩略图的路径
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+    // 复制来源的窗口, 拿不到的时候是 None
+    #[serde(default)]
+    pub source: Option<ClipboardSource>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ClipboardListResult {
-    pub result: Vec<String>,
+    pub result: Vec<ClipboardListItem>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ClipboardSetByIdxRequest {
     pub idx: usize,
+    #[serde(default)]
+    pub target: ClipboardSetTarget,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardSetByIdRequest {
+    pub id: u64,
+    #[serde(default)]
+    pub target: ClipboardSetTarget,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardPinByIdxRequest {
+    pub idx: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardPinByIdRequest {
+    pub id: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardGetByIdxRequest {
+    pub idx: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardGetByIdRequest {
+    pub id: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardGetResult {
+    pub item: ClipboardListItem,
+    // 文本/HTML/URL 的完整内容, 图片和二进制数据是 None
+    pub content: Option<String>,
+    // 图片可以通过 /image/{hash}.png 获取
+    pub image_hash: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardSearchRequest {
+    pub query: String,
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub ignore_case: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardSearchHit {
+    pub idx: usize,
+    pub id: u64,
+    // pango markup, 命中的部分用 <b> 包起来
+    pub snippet: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardSearchResult {
+    pub result: Vec<ClipboardSearchHit>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardDeleteByIdxRequest {
+    pub idx: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardDeleteByIdRequest {
+    pub id: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardClearRequest {
+    #[serde(default)]
+    pub keep_pinned: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardDeleteMatchingRequest {
+    pub regex: String,
+    #[serde(default)]
+    pub keep_pinned: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardDeleteResult {
+    pub deleted: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardImportResult {
+    pub imported: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardHealthResult {
+    // clipd 的版本
+    pub version: String,
+    pub api_version: u32,
+    pub pid: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardUnlockResult {
+    // 从磁盘合并进来的条目数
+    pub loaded: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardStatsResult {
+    pub entry_count: usize,
+    pub pinned_count: usize,
+    // 所有条目占用的字节数, 图片按解码后的像素数据计算
+    pub total_bytes: usize,
+    pub image_bytes: usize,
+    // 0 代表不限制
+    pub max_size: usize,
+    pub max_primary_size: usize,
+    pub max_entry_bytes: usize,
+    pub max_total_bytes: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardErrorResult {
+    pub error: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardEventKind {
+    // 新复制的内容
+    New,
+    // 已有的条目被重新设置到剪贴板
+    MovedToFront,
+    // 条目被删除, item 是删除的条目
+    Removed,
+}
+
+// /subscribe 每行一个 json
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClipboardEvent {
+    pub event: ClipboardEventKind,
+    pub item: ClipboardListItem,
+}
+
+// /get_latest 返回的原始数据
+#[derive(Clone, Debug)]
+pub struct ClipboardContent {
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+pub struct ClipboardSubscription {
+    reader: std::io::BufReader<isahc::Body>,
+}
+
+impl Iterator for ClipboardSubscription {
+    type Item = ClipboardEvent;
+
+    // clipd 退出的时候结束
+    fn next(&mut self) -> Option<ClipboardEvent> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => {}
+            }
+            match serde_json::from_str(&line) {
+                Ok(event) => return Some(event),
+                Err(err) => log::error!("invalid clipboard event: {}", err),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ClipdError {
+    // socket 不存在或者没有进程在监听
+    DaemonNotRunning(String),
+    PermissionDenied(String),
+    // 路由不存在或者 api 版本不一致, 一般是 clipd 和客户端不是同一个版本
+    ProtocolMismatch(String),
+    BadJson(serde_json::Error),
+    // clipd 处理请求时返回的错误, 例如正则写错了
+    Server(String),
+    Io(std::io::Error),
+    // clipd 没有运行, 直接读写剪贴板也失败了
+    Fallback(String),
+}
+
+impl std::fmt::Display for ClipdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::DaemonNotRunning(path) => write!(f, "clipd is not running ({})", path),
+            Self::PermissionDenied(path) => write!(f, "permission denied on clipd socket {}", path),
+            Self::ProtocolMismatch(msg) => {
+                write!(f, "clipd protocol mismatch: {}, restart clipd", msg)
+            }
+            Self::BadJson(err) => write!(f, "bad response from clipd: {}", err),
+            Self::Server(msg) => write!(f, "{}", msg),
+            Self::Io(err) => write!(f, "clipd request failed: {}", err),
+            Self::Fallback(msg) => write!(f, "clipd is not running, {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ClipdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::BadJson(err) => Some(err),
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ClipdError {
+    fn from(err: serde_json::Error) -> Self {
+        ClipdError::BadJson(err)
+    }
+}
+
+pub type ClipdResult<T> = Result<T, ClipdError>;
+
+// clipd 没有运行时直接读写剪贴板的方式
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClipboardFallback {
+    // wayland 下优先 wl-clipboard, 否则 xclip, 再否则 xsel
+    #[default]
+    Auto,
+    WlClipboard,
+    Xclip,
+    Xsel,
+    None,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ClipboardConfig {
+    pub fallback: ClipboardFallback,
+}
+
+// 每个 ClipdClient 都会读取, 只读不写, 没有配置的时候用默认值
+fn read_config() -> ClipboardConfig {
+    file::config_restore_from_file("clipboard").unwrap_or_default()
+}
+
+fn command_exists(name: &str) -> bool {
+    match std::env::var_os("PATH") {
+        Some(paths) => std::env::split_paths(&paths).any(|dir| dir.join(name).is_file()),
+        None => false,
+    }
 }
 
-pub fn clipboard_get_text() -> String {
-    let request = get_clipd_request(CLIPBOARD_GET_LATEST_TEXT)
-        .method(http::Method::GET)
-        .body(())
-        .unwrap();
-    let response: ClipboardGetLatestTextResult = serde_json::from_slice(
-        request
-            .send()
-            .expect("sending request failed")
-            .bytes()
-            .expect("reading response failed")
-            .as_ref(),
-    )
-    .expect("recv response failed");
-    return response.result;
-}
-
-pub fn clipboard_set_text(text: &str) {
-    let request = get_clipd_request(CLIPBOARD_SET_TEXT)
-        .method(http::Method::POST)
-        .header(http::header::CONTENT_TYPE, "application/json")
-        .body(
-            serde_json::to_vec(&ClipboardSetTextRequest {
+impl ClipboardFallback {
+    // Auto 换成实际可用的工具, 都没有的话返回 None
+    fn resolve(self) -> ClipboardFallback {
+        if self != ClipboardFallback::Auto {
+            return self;
+        }
+
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() && command_exists("wl-paste") {
+            ClipboardFallback::WlClipboard
+        } else if command_exists("xclip") {
+            ClipboardFallback::Xclip
+        } else if command_exists("xsel") {
+            ClipboardFallback::Xsel
+        } else {
+            ClipboardFallback::None
+        }
+    }
+
+    fn get_command(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            Self::WlClipboard => Some(("wl-paste", &["--no-newline"])),
+            Self::Xclip => Some(("xclip", &["-selection", "clipboard", "-o"])),
+            Self::Xsel => Some(("xsel", &["--clipboard", "--output"])),
+            Self::Auto | Self::None => None,
+        }
+    }
+
+    fn set_command(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            Self::WlClipboard => Some(("wl-copy", &[])),
+            Self::Xclip => Some(("xclip", &["-selection", "clipboard", "-i"])),
+            Self::Xsel => Some(("xsel", &["--clipboard", "--input"])),
+            Self::Auto | Self::None => None,
+        }
+    }
+
+    fn get_text(self) -> Option<ClipdResult<String>> {
+        let (program, args) = self.get_command()?;
+        let output = std::process::Command::new(program)
+            .args(args)
+            .stdin(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .output();
+
+        return Some(match output {
+            Ok(output) if output.status.success() => {
+                Ok(String::from_utf8_lossy(&output.stdout).to_string())
+            }
+            Ok(output) => Err(ClipdError::Fallback(format!(
+                "{} exited with {}",
+                program, output.status
+            ))),
+            Err(err) => Err(ClipdError::Fallback(format!("{}: {}", program, err))),
+        });
+    }
+
+    fn set_text(self, text: &str) -> Option<ClipdResult<()>> {
+        let (program, args) = self.set_command()?;
+
+        let run = || -> std::io::Result<std::process::ExitStatus> {
+            // 这些工具会在后台常驻提供剪贴板内容, stdout 不能继承
+            // 不然 rofi 会一直等到它退出
+            let mut child = std::process::Command::new(program)
+                .args(args)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn()?;
+            child.stdin.take().unwrap().write_all(text.as_bytes())?;
+            child.wait()
+        };
+
+        return Some(match run() {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(ClipdError::Fallback(format!(
+                "{} exited with {}",
+                program, status
+            ))),
+            Err(err) => Err(ClipdError::Fallback(format!("{}: {}", program, err))),
+        });
+    }
+}
+
+pub struct ClipdClient {
+    socket_path: String,
+    fallback: ClipboardFallback,
+}
+
+impl Default for ClipdClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClipdClient {
+    pub fn new() -> ClipdClient {
+        return ClipdClient::with_socket_path(get_clipd_listen_path());
+    }
+
+    pub fn with_socket_path(socket_path: String) -> ClipdClient {
+        return ClipdClient {
+            socket_path,
+            fallback: read_config().fallback,
+        };
+    }
+
+    fn request(&self, path: &str) -> Builder {
+        Builder::new()
+            .uri(format!("http://localhost{}", path))
+            .dial(Dialer::unix_socket(&self.socket_path))
+    }
+
+    fn map_send_error(&self, err: isahc::Error) -> ClipdError {
+        // 先看底层的 io 错误, 能区分 socket 权限问题和残留的 socket 文件
+        let mut source = std::error::Error::source(&err);
+        while let Some(inner) = source {
+            if let Some(io_err) = inner.downcast_ref::<std::io::Error>() {
+                match io_err.kind() {
+                    std::io::ErrorKind::PermissionDenied => {
+                        return ClipdError::PermissionDenied(self.socket_path.clone())
+                    }
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused => {
+                        return ClipdError::DaemonNotRunning(self.socket_path.clone())
+                    }
+                    _ => {}
+                }
+            }
+            source = inner.source();
+        }
+
+        match err.kind() {
+            isahc::error::ErrorKind::ConnectionFailed => {
+                ClipdError::DaemonNotRunning(self.socket_path.clone())
+            }
+            _ => ClipdError::Io(err.into()),
+        }
+    }
+
+    fn send(&self, request: http::Request<Vec<u8>>) -> ClipdResult<isahc::Response<isahc::Body>> {
+        match std::fs::metadata(&self.socket_path) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(ClipdError::DaemonNotRunning(self.socket_path.clone()));
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+                return Err(ClipdError::PermissionDenied(self.socket_path.clone()));
+            }
+            // 不是自己的 socket, 可能是其他用户在 /tmp 下抢先创建的
+            Ok(md) if md.uid() != rustix::process::getuid().as_raw() => {
+                return Err(ClipdError::PermissionDenied(self.socket_path.clone()));
+            }
+            _ => {}
+        }
+
+        let path = request.uri().path().to_string();
+        let mut response = request.send().map_err(|err| self.map_send_error(err))?;
+
+        if let Some(version) = response.headers().get(CLIPD_API_VERSION_HEADER) {
+            let version = version.to_str().unwrap_or_default();
+            if version != CLIPD_API_VERSION.to_string() {
+                return Err(ClipdError::ProtocolMismatch(format!(
+                    "clipd api version {}, expected {}",
+                    version, CLIPD_API_VERSION
+                )));
+            }
+        }
+
+        let status = response.status();
+        if !status.is_success() {
+            let response_data = response.bytes().map_err(ClipdError::Io)?;
+            // 路由存在但是条目不存在的时候也是 404, 这时候会带上错误信息
+            return match serde_json::from_slice::<ClipboardErrorResult>(&response_data) {
+                Ok(error) => Err(ClipdError::Server(error.error)),
+                Err(_) if status == http::StatusCode::NOT_FOUND => Err(
+                    ClipdError::ProtocolMismatch(format!("{} not supported", path)),
+                ),
+                Err(_) => Err(ClipdError::ProtocolMismatch(format!(
+                    "unexpected status {}",
+                    status
+                ))),
+            };
+        }
+
+        return Ok(response);
+    }
+
+    fn get<R: serde::de::DeserializeOwned>(&self, path: &str) -> ClipdResult<R> {
+        let request = self
+            .request(path)
+            .method(http::Method::GET)
+            .body(Vec::new())
+            .unwrap();
+        let response_data = self.send(request)?.bytes().map_err(ClipdError::Io)?;
+        return Ok(serde_json::from_slice(&response_data)?);
+    }
+
+    fn post<T: Serialize>(&self, path: &str, body: &T) -> ClipdResult<Vec<u8>> {
+        let request = self
+            .request(path)
+            .method(http::Method::POST)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(body)?)
+            .unwrap();
+        return self.send(request)?.bytes().map_err(ClipdError::Io);
+    }
+
+    fn post_json<T: Serialize, R: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> ClipdResult<R> {
+        let response_data = self.post(path, body)?;
+        return Ok(serde_json::from_slice(&response_data)?);
+    }
+
+    // clipd 没有运行的时候按配置直接读剪贴板
+    pub fn get_latest_text(&self) -> ClipdResult<String> {
+        match self.get::<ClipboardGetLatestTextResult>(CLIPBOARD_GET_LATEST_TEXT) {
+            Ok(response) => Ok(response.result),
+            Err(ClipdError::DaemonNotRunning(path)) => self
+                .fallback
+                .resolve()
+                .get_text()
+                .unwrap_or(Err(ClipdError::DaemonNotRunning(path))),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn set_text(&self, text: &str) -> ClipdResult<()> {
+        let result = self.post(
+            CLIPBOARD_SET_TEXT,
+            &ClipboardSetTextRequest {
                 text: text.to_owned(),
-            })
-            .unwrap(),
-        )
-        .unwrap();
-    request.send().expect("sending request failed");
-}
-
-pub fn clipboard_list() -> Vec<String> {
-    let request = get_clipd_request(CLIPBOARD_LIST)
-        .method(http::Method::GET)
-        .body(())
-        .unwrap();
-    let response: ClipboardListResult = serde_json::from_slice(
-        request
-            .send()
-            .expect("sending request failed")
-            .bytes()
-            .expect("reading response failed")
-            .as_ref(),
-    )
-    .expect("recv response failed");
-    return response.result;
-}
-
-pub fn clipboard_set_by_idx(idx: usize) {
-    let request = get_clipd_request(CLIPBOARD_SET_BY_IDX)
-        .method(http::Method::POST)
-        .header(http::header::CONTENT_TYPE, "application/json")
-        .body(serde_json::to_vec(&ClipboardSetByIdxRequest { idx }).unwrap())
-        .unwrap();
-    request.send().expect("sending request failed");
+            },
+        );
+        match result {
+            Ok(_) => Ok(()),
+            Err(ClipdError::DaemonNotRunning(path)) => self
+                .fallback
+                .resolve()
+                .set_text(text)
+                .unwrap_or(Err(ClipdError::DaemonNotRunning(path))),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get_latest(&self) -> ClipdResult<ClipboardContent> {
+        let request = self
+            .request(CLIPBOARD_GET_LATEST)
+            .method(http::Method::GET)
+            .body(Vec::new())
+            .unwrap();
+        let mut response = match self.send(request) {
+            Ok(x) => x,
+            Err(ClipdError::DaemonNotRunning(path)) => {
+                // 直接读剪贴板只能拿到文本
+                return match self.fallback.resolve().get_text() {
+                    Some(text) => Ok(ClipboardContent {
+                        mime: "text/plain;charset=utf-8".to_string(),
+                        data: text?.into_bytes(),
+                    }),
+                    None => Err(ClipdError::DaemonNotRunning(path)),
+                };
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mime = response
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let data = response.bytes().map_err(ClipdError::Io)?;
+        return Ok(ClipboardContent { mime, data });
+    }
+
+    // 文本会按 mime 转换成对应的类型, 图片会解码, 其他的原样保存
+    pub fn set(&self, data: &[u8], mime: &str) -> ClipdResult<()> {
+        let request = self
+            .request(CLIPBOARD_SET)
+            .method(http::Method::POST)
+            .header(http::header::CONTENT_TYPE, mime)
+            .body(data.to_vec())
+            .unwrap();
+        match self.send(request) {
+            Ok(_) => Ok(()),
+            Err(ClipdError::DaemonNotRunning(path)) => {
+                let text = std::str::from_utf8(data)
+                    .ok()
+                    .filter(|_| mime.starts_with("text/plain"));
+                match text.and_then(|text| self.fallback.resolve().set_text(text)) {
+                    Some(result) => result,
+                    None => Err(ClipdError::DaemonNotRunning(path)),
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // data 是 PNG/JPEG 等格式的图片
+    pub fn set_image(&self, data: &[u8]) -> ClipdResult<()> {
+        let request = self
+            .request(CLIPBOARD_SET_IMAGE)
+            .method(http::Method::POST)
+            .header(http::header::CONTENT_TYPE, "application/octet-stream")
+            .body(data.to_vec())
+            .unwrap();
+        self.send(request)?;
+        return Ok(());
+    }
+
+    pub fn set_uris(&self, uris: &[String]) -> ClipdResult<()> {
+        self.post(
+            CLIPBOARD_SET_URIS,
+            &ClipboardSetUrisRequest {
+                uris: uris.to_vec(),
+            },
+        )?;
+        return Ok(());
+    }
+
+    pub fn list(&self) -> ClipdResult<Vec<ClipboardListItem>> {
+        let response: ClipboardListResult = self.get(CLIPBOARD_LIST)?;
+        return Ok(response.result);
+    }
+
+    pub fn stats(&self) -> ClipdResult<ClipboardStatsResult> {
+        return self.get(CLIPBOARD_STATS);
+    }
+
+    pub fn export(&self) -> ClipdResult<Vec<u8>> {
+        let request = self
+            .request(CLIPBOARD_EXPORT)
+            .method(http::Method::GET)
+            .body(Vec::new())
+            .unwrap();
+        return self.send(request)?.bytes().map_err(ClipdError::Io);
+    }
+
+    pub fn import(&self, archive: &[u8]) -> ClipdResult<usize> {
+        let request = self
+            .request(CLIPBOARD_IMPORT)
+            .method(http::Method::POST)
+            .header(http::header::CONTENT_TYPE, "application/x-tar")
+            .body(archive.to_vec())
+            .unwrap();
+        let response: ClipboardImportResult =
+            serde_json::from_slice(&self.send(request)?.bytes().map_err(ClipdError::Io)?)?;
+        return Ok(response.imported);
+    }
+
+    pub fn health(&self) -> ClipdResult<ClipboardHealthResult> {
+        return self.get(CLIPBOARD_HEALTH);
+    }
+
+    // 让正在运行的 clipd 退出, 返回它退出前导出的历史记录, 格式和 export 相同
+    pub fn shutdown(&self) -> ClipdResult<Vec<u8>> {
+        let request = self
+            .request(CLIPBOARD_SHUTDOWN)
+            .method(http::Method::POST)
+            .body(Vec::new())
+            .unwrap();
+        return self.send(request)?.bytes().map_err(ClipdError::Io);
+    }
+
+    // 需要先用 file::unlock 解锁
+    pub fn unlock(&self) -> ClipdResult<usize> {
+        let response: ClipboardUnlockResult = self.post_json(CLIPBOARD_UNLOCK, &())?;
+        return Ok(response.loaded);
+    }
+
+    pub fn lock(&self) -> ClipdResult<()> {
+        self.post(CLIPBOARD_LOCK, &())?;
+        return Ok(());
+    }
+
+    pub fn set_by_idx(&self, idx: usize, target: ClipboardSetTarget) -> ClipdResult<()> {
+        self.post(
+            CLIPBOARD_SET_BY_IDX,
+            &ClipboardSetByIdxRequest { idx, target },
+        )?;
+        return Ok(());
+    }
+
+    pub fn set_by_id(&self, id: u64, target: ClipboardSetTarget) -> ClipdResult<()> {
+        self.post(CLIPBOARD_SET_BY_ID, &ClipboardSetByIdRequest { id, target })?;
+        return Ok(());
+    }
+
+    pub fn pin_by_idx(&self, idx: usize) -> ClipdResult<()> {
+        self.post(CLIPBOARD_PIN_BY_IDX, &ClipboardPinByIdxRequest { idx })?;
+        return Ok(());
+    }
+
+    pub fn pin_by_id(&self, id: u64) -> ClipdResult<()> {
+        self.post(CLIPBOARD_PIN_BY_ID, &ClipboardPinByIdRequest { id })?;
+        return Ok(());
+    }
+
+    pub fn unpin_by_idx(&self, idx: usize) -> ClipdResult<()> {
+        self.post(CLIPBOARD_UNPIN_BY_IDX, &ClipboardPinByIdxRequest { idx })?;
+        return Ok(());
+    }
+
+    pub fn unpin_by_id(&self, id: u64) -> ClipdResult<()> {
+        self.post(CLIPBOARD_UNPIN_BY_ID, &ClipboardPinByIdRequest { id })?;
+        return Ok(());
+    }
+
+    pub fn get_by_idx(&self, idx: usize) -> ClipdResult<ClipboardGetResult> {
+        return self.post_json(CLIPBOARD_GET_BY_IDX, &ClipboardGetByIdxRequest { idx });
+    }
+
+    pub fn get_by_id(&self, id: u64) -> ClipdResult<ClipboardGetResult> {
+        return self.post_json(CLIPBOARD_GET_BY_ID, &ClipboardGetByIdRequest { id });
+    }
+
+    // 返回 png 编码的图片
+    pub fn get_image(&self, hash: &str) -> ClipdResult<Vec<u8>> {
+        let request = self
+            .request(&format!("{}/{}.png", CLIPBOARD_IMAGE, hash))
+            .method(http::Method::GET)
+            .body(Vec::new())
+            .unwrap();
+        return self.send(request)?.bytes().map_err(ClipdError::Io);
+    }
+
+    pub fn search(
+        &self,
+        query: &str,
+        regex: bool,
+        ignore_case: bool,
+    ) -> ClipdResult<Vec<ClipboardSearchHit>> {
+        let response: ClipboardSearchResult = self.post_json(
+            CLIPBOARD_SEARCH,
+            &ClipboardSearchRequest {
+                query: query.to_owned(),
+                regex,
+                ignore_case,
+            },
+        )?;
+        return Ok(response.result);
+    }
+
+    pub fn delete_by_idx(&self, idx: usize) -> ClipdResult<()> {
+        self.post(
+            CLIPBOARD_DELETE_BY_IDX,
+            &ClipboardDeleteByIdxRequest { idx },
+        )?;
+        return Ok(());
+    }
+
+    pub fn delete_by_id(&self, id: u64) -> ClipdResult<()> {
+        self.post(CLIPBOARD_DELETE_BY_ID, &ClipboardDeleteByIdRequest { id })?;
+        return Ok(());
+    }
+
+    pub fn clear(&self, keep_pinned: bool) -> ClipdResult<usize> {
+        let response: ClipboardDeleteResult =
+            self.post_json(CLIPBOARD_CLEAR, &ClipboardClearRequest { keep_pinned })?;
+        return Ok(response.deleted);
+    }
+
+    pub fn delete_matching(&self, regex: &str, keep_pinned: bool) -> ClipdResult<usize> {
+        let response: ClipboardDeleteResult = self.post_json(
+            CLIPBOARD_DELETE_MATCHING,
+            &ClipboardDeleteMatchingRequest {
+                regex: regex.to_owned(),
+                keep_pinned,
+            },
+        )?;
+        return Ok(response.deleted);
+    }
+
+    pub fn subscribe(&self) -> ClipdResult<ClipboardSubscription> {
+        let request = self
+            .request(CLIPBOARD_SUBSCRIBE)
+            .method(http::Method::GET)
+            .body(Vec::new())
+            .unwrap();
+        let response = self.send(request)?;
+        return Ok(ClipboardSubscription {
+            reader: std::io::BufReader::new(response.into_body()),
+        });
+    }
 }
@@ -1,7 +1,44 @@
-use std::{collections::HashMap, fs};
+use base64::engine::general_purpose as base64_engine;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use std::io::{Read, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::time::Duration;
+use std::{collections::HashMap, fs, path::Path, path::PathBuf};
 
 use crate::rofi::RofiPluginError;
 
+pub fn get_tmp_dir() -> String {
+    // 先尝试 XDG_RUNTIME_DIR (/run/user/1000), 不行用 /tmp/
+    if let Ok(tmp_dir) = std::env::var("XDG_RUNTIME_DIR") {
+        let md = std::fs::metadata(&tmp_dir);
+        if let Ok(md) = md {
+            let permissions = md.permissions();
+            if !permissions.readonly() {
+                return tmp_dir;
+            }
+        }
+    }
+
+    return std::env::temp_dir().to_string_lossy().to_string();
+}
+
+pub fn get_data_dir(name: &str) -> anyhow::Result<PathBuf> {
+    let data_dir = dirs::data_dir().unwrap().join("rofi-toys").join(name);
+    fs::create_dir_all(&data_dir)?;
+
+    Ok(data_dir)
+}
+
+pub fn get_cache_dir(name: &str) -> anyhow::Result<PathBuf> {
+    let cache_dir = dirs::cache_dir().unwrap().join("rofi-toys").join(name);
+    fs::create_dir_all(&cache_dir)?;
+
+    Ok(cache_dir)
+}
+
 pub fn storage_save_to_file<T: serde::Serialize>(object: T, name: &str) -> anyhow::Result<()> {
     let data_dir = dirs::data_dir().unwrap().join("rofi-toys");
     fs::create_dir_all(&data_dir)?;
@@ -29,10 +66,21 @@ pub fn storage_restore_from_file<T: serde::de::DeserializeOwned>(name: &str) ->
 
     match map.get(name) {
         Some(x) => Ok(serde_json::from_value(x.clone())?),
-        None => Err(RofiPluginError::new(&format!("no such key:{} in map",name)).into()),
+        // 和 storage.json 不存在一样当作 NotFound, 调用方用 is_not_found 判断
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no such key:{} in map",name),
+        )
+        .into()),
     }
 }
 
+// 数据还没有保存过, 其他错误 (解析失败, 解密失败等) 不能当成没有数据
+pub fn is_not_found(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|x| x.kind() == std::io::ErrorKind::NotFound)
+}
+
 pub fn config_save_to_file<T: serde::Serialize>(object: T, name: &str) -> anyhow::Result<()> {
     let data_dir = dirs::data_dir().unwrap().join("rofi-toys");
     fs::create_dir_all(&data_dir)?;
@@ -63,3 +111,385 @@ pub fn config_restore_from_file<T: serde::de::DeserializeOwned>(name: &str) -> a
         None => Err(RofiPluginError::new(&format!("no such key:{} in map",name)).into()),
     }
 }
+
+// 加密文件的开头, 后面是 24 字节的 nonce 和密文
+const ENCRYPTED_MAGIC: &[u8] = b"RTENC1\0";
+const NONCE_SIZE: usize = 24;
+// 用来校验 passphrase 是否正确
+const KEY_CHECK_DATA: &[u8] = b"rofi-toys";
+
+const SECRET_SERVICE_NAME: &str = "org.freedesktop.secrets";
+const SECRET_SERVICE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(serde::Serialize, serde::Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum EncryptionKeySource {
+    // 通过 argon2 从 passphrase 派生, 第一次解锁前用 set_passphrase 设置
+    #[default]
+    Passphrase,
+    // 随机生成后保存在 Secret Service (gnome-keyring, kwallet 等)
+    SecretService,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    // 开启后 notes 和 clipd 的历史记录加密保存
+    pub enabled: bool,
+    pub key_source: EncryptionKeySource,
+}
+
+// 每次读写 notes 和 clipc 的操作都会读取, 只读不写, 没有配置的时候不加密
+pub fn encryption_config() -> EncryptionConfig {
+    config_restore_from_file("encryption").unwrap_or_default()
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PassphraseParams {
+    // base64
+    salt: String,
+    check: String,
+}
+
+// 存在 storage.json 里的加密数据
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EncryptedValue {
+    // base64
+    encrypted: String,
+}
+
+pub struct EncryptionKey([u8; 32]);
+
+// 不要把 key 打到日志里
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+impl EncryptionKey {
+    fn generate() -> EncryptionKey {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        return EncryptionKey(key);
+    }
+
+    fn from_slice(data: &[u8]) -> anyhow::Result<EncryptionKey> {
+        match <[u8; 32]>::try_from(data) {
+            Ok(key) => Ok(EncryptionKey(key)),
+            Err(_) => Err(RofiPluginError::new("invalid encryption key").into()),
+        }
+    }
+
+    fn derive(passphrase: &str, salt: &[u8]) -> anyhow::Result<EncryptionKey> {
+        let mut key = [0u8; 32];
+        if let Err(err) =
+            argon2::Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        {
+            return Err(RofiPluginError::new(&format!("failed to derive key: {}", err)).into());
+        }
+        return Ok(EncryptionKey(key));
+    }
+
+    pub fn encrypt(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&self.0));
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = match cipher.encrypt(XNonce::from_slice(&nonce), data) {
+            Ok(x) => x,
+            Err(_) => return Err(RofiPluginError::new("failed to encrypt data").into()),
+        };
+
+        let mut result = Vec::with_capacity(ENCRYPTED_MAGIC.len() + NONCE_SIZE + ciphertext.len());
+        result.extend_from_slice(ENCRYPTED_MAGIC);
+        result.extend_from_slice(&nonce);
+        result.extend_from_slice(&ciphertext);
+        return Ok(result);
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let data = match data.strip_prefix(ENCRYPTED_MAGIC) {
+            Some(x) if x.len() >= NONCE_SIZE => x,
+            _ => return Err(RofiPluginError::new("data is not encrypted").into()),
+        };
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&self.0));
+        match cipher.decrypt(XNonce::from_slice(nonce), ciphertext) {
+            Ok(x) => Ok(x),
+            Err(_) => Err(RofiPluginError::new("failed to decrypt data").into()),
+        }
+    }
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(ENCRYPTED_MAGIC)
+}
+
+pub fn is_encrypted_file(path: &Path) -> bool {
+    let mut magic = [0u8; ENCRYPTED_MAGIC.len()];
+    match fs::File::open(path).and_then(|mut x| x.read_exact(&mut magic)) {
+        Ok(_) => is_encrypted(&magic),
+        Err(_) => false,
+    }
+}
+
+// 解锁后 key 缓存在运行时目录, 直到 lock 或者重启
+// 只用 XDG_RUNTIME_DIR, /tmp 是所有用户共享的, 没有的话不缓存
+fn key_cache_path() -> Option<PathBuf> {
+    let runtime_dir = PathBuf::from(std::env::var_os("XDG_RUNTIME_DIR")?);
+    if !runtime_dir.is_absolute() || !runtime_dir.is_dir() {
+        return None;
+    }
+    Some(runtime_dir.join("rofi-toys.key"))
+}
+
+pub fn cached_key() -> Option<EncryptionKey> {
+    let mut key_file = fs::File::open(key_cache_path()?).ok()?;
+    // 不是自己创建的, 或者其他人能读写的 key 不能信任
+    let metadata = key_file.metadata().ok()?;
+    if !metadata.is_file()
+        || metadata.uid() != rustix::process::getuid().as_raw()
+        || metadata.mode() & 0o777 != 0o600
+    {
+        return None;
+    }
+    let mut data = Vec::new();
+    key_file.read_to_end(&mut data).ok()?;
+    EncryptionKey::from_slice(&data).ok()
+}
+
+pub fn is_locked() -> bool {
+    encryption_config().enabled && cached_key().is_none()
+}
+
+// 加密了但还没解锁的时候不能读写, 否则会把原来的数据当成空的覆盖掉
+pub fn ensure_unlocked() -> anyhow::Result<()> {
+    if is_locked() {
+        return Err(RofiPluginError::new("storage is locked, unlock it first").into());
+    }
+    Ok(())
+}
+
+fn cache_key(key: &EncryptionKey) -> anyhow::Result<()> {
+    let key_path = match key_cache_path() {
+        Some(x) => x,
+        None => {
+            return Err(
+                RofiPluginError::new("XDG_RUNTIME_DIR is not available, can't unlock").into(),
+            )
+        }
+    };
+    // 先删掉, 保证新建的文件权限是 0600
+    let _ = fs::remove_file(&key_path);
+    let mut key_file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&key_path)?;
+    key_file.write_all(&key.0)?;
+    Ok(())
+}
+
+fn passphrase_params_path() -> anyhow::Result<PathBuf> {
+    let data_dir = dirs::data_dir().unwrap().join("rofi-toys");
+    fs::create_dir_all(&data_dir)?;
+
+    Ok(data_dir.join("passphrase.json"))
+}
+
+// 还没有设置过 passphrase 的时候需要先用 set_passphrase 设置
+pub fn has_passphrase() -> bool {
+    passphrase_params_path().is_ok_and(|x| x.exists())
+}
+
+fn passphrase_key(passphrase: &str) -> anyhow::Result<EncryptionKey> {
+    let params_data = match fs::read_to_string(passphrase_params_path()?) {
+        Ok(x) => x,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(RofiPluginError::new("passphrase is not set yet").into())
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let params: PassphraseParams = serde_json::from_str(&params_data)?;
+    let key = EncryptionKey::derive(passphrase, &base64_engine::STANDARD.decode(params.salt)?)?;
+    if key
+        .decrypt(&base64_engine::STANDARD.decode(params.check)?)
+        .is_err()
+    {
+        return Err(RofiPluginError::new("wrong passphrase").into());
+    }
+    return Ok(key);
+}
+
+// 第一次解锁时设置 passphrase, 输错了就没法解密了, 所以要输入两次
+pub fn set_passphrase(passphrase: &str, confirm: &str) -> anyhow::Result<()> {
+    if passphrase.is_empty() {
+        return Err(RofiPluginError::new("passphrase can't be empty").into());
+    }
+    if passphrase != confirm {
+        return Err(RofiPluginError::new("passphrases do not match").into());
+    }
+
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let key = EncryptionKey::derive(passphrase, &salt)?;
+    let params = PassphraseParams {
+        salt: base64_engine::STANDARD.encode(salt),
+        check: base64_engine::STANDARD.encode(key.encrypt(KEY_CHECK_DATA)?),
+    };
+    // 已经设置过的话不覆盖, 否则之前加密的数据就解不开了
+    let mut params_file = match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(passphrase_params_path()?)
+    {
+        Ok(x) => x,
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+            return Err(RofiPluginError::new("passphrase is already set").into())
+        }
+        Err(err) => return Err(err.into()),
+    };
+    params_file.write_all(serde_json::to_string(&params)?.as_bytes())?;
+    cache_key(&key)
+}
+
+fn secret_service_key() -> anyhow::Result<EncryptionKey> {
+    use dbus::arg::{PropMap, RefArg, Variant};
+
+    let conn = dbus::blocking::Connection::new_session()?;
+    let service = conn.with_proxy(
+        SECRET_SERVICE_NAME,
+        "/org/freedesktop/secrets",
+        SECRET_SERVICE_TIMEOUT,
+    );
+
+    // plain 方式只在本机的 session bus 上传输
+    let (_, session): (Variant<Box<dyn RefArg>>, dbus::Path<'static>) = service.method_call(
+        "org.freedesktop.Secret.Service",
+        "OpenSession",
+        ("plain", Variant(String::new())),
+    )?;
+
+    let attributes = HashMap::from([("application".to_string(), "rofi-toys".to_string())]);
+    let (unlocked, locked): (Vec<dbus::Path<'static>>, Vec<dbus::Path<'static>>) = service
+        .method_call(
+            "org.freedesktop.Secret.Service",
+            "SearchItems",
+            (attributes.clone(),),
+        )?;
+
+    if let Some(item) = unlocked.first() {
+        let item = conn.with_proxy(SECRET_SERVICE_NAME, item, SECRET_SERVICE_TIMEOUT);
+        let ((_, _, value, _),): ((dbus::Path<'static>, Vec<u8>, Vec<u8>, String),) =
+            item.method_call("org.freedesktop.Secret.Item", "GetSecret", (session,))?;
+        return EncryptionKey::from_slice(&value);
+    }
+    if !locked.is_empty() {
+        return Err(RofiPluginError::new("keyring is locked, unlock it first").into());
+    }
+
+    // 还没有的话生成一个新的 key 存进默认的 collection
+    let (collection,): (dbus::Path<'static>,) =
+        service.method_call("org.freedesktop.Secret.Service", "ReadAlias", ("default",))?;
+    if &*collection == "/" {
+        return Err(RofiPluginError::new("no default keyring collection").into());
+    }
+
+    let key = EncryptionKey::generate();
+    let mut properties: PropMap = HashMap::new();
+    properties.insert(
+        "org.freedesktop.Secret.Item.Label".to_string(),
+        Variant(Box::new("rofi-toys storage key".to_string())),
+    );
+    properties.insert(
+        "org.freedesktop.Secret.Item.Attributes".to_string(),
+        Variant(Box::new(attributes)),
+    );
+
+    let collection = conn.with_proxy(SECRET_SERVICE_NAME, collection, SECRET_SERVICE_TIMEOUT);
+    let (_, prompt): (dbus::Path<'static>, dbus::Path<'static>) = collection.method_call(
+        "org.freedesktop.Secret.Collection",
+        "CreateItem",
+        (
+            properties,
+            (
+                session,
+                Vec::<u8>::new(),
+                key.0.to_vec(),
+                "application/octet-stream",
+            ),
+            false,
+        ),
+    )?;
+    if &*prompt != "/" {
+        return Err(RofiPluginError::new("keyring is locked, unlock it first").into());
+    }
+
+    return Ok(key);
+}
+
+// passphrase 只有 key_source 是 passphrase 的时候需要
+pub fn unlock(passphrase: Option<&str>) -> anyhow::Result<()> {
+    let key = match encryption_config().key_source {
+        EncryptionKeySource::Passphrase => match passphrase {
+            Some(passphrase) => passphrase_key(passphrase)?,
+            None => return Err(RofiPluginError::new("passphrase required").into()),
+        },
+        EncryptionKeySource::SecretService => secret_service_key()?,
+    };
+    cache_key(&key)
+}
+
+pub fn lock() -> anyhow::Result<()> {
+    let key_path = match key_cache_path() {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    match fs::remove_file(key_path) {
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// 开启加密的时候加密后再保存, 否则和 storage_save_to_file 相同
+pub fn secure_storage_save_to_file<T: serde::Serialize>(
+    object: T,
+    name: &str,
+) -> anyhow::Result<()> {
+    if !encryption_config().enabled {
+        return storage_save_to_file(object, name);
+    }
+
+    let key = match cached_key() {
+        Some(x) => x,
+        None => return Err(RofiPluginError::new("storage is locked, unlock it first").into()),
+    };
+    let encrypted = key.encrypt(&serde_json::to_vec(&object)?)?;
+    storage_save_to_file(
+        EncryptedValue {
+            encrypted: base64_engine::STANDARD.encode(encrypted),
+        },
+        name,
+    )
+}
+
+// 没有加密的旧数据直接读取, 下次保存时再加密
+pub fn secure_storage_restore_from_file<T: serde::de::DeserializeOwned>(
+    name: &str,
+) -> anyhow::Result<T> {
+    let value: serde_json::Value = storage_restore_from_file(name)?;
+    let encrypted = match serde_json::from_value::<EncryptedValue>(value.clone()) {
+        Ok(x) => x,
+        Err(_) => return Ok(serde_json::from_value(value)?),
+    };
+
+    let key = match cached_key() {
+        Some(x) => x,
+        None => return Err(RofiPluginError::new("storage is locked, unlock it first").into()),
+    };
+    let data = key.decrypt(&base64_engine::STANDARD.decode(encrypted.encrypted)?)?;
+    Ok(serde_json::from_slice(&data)?)
+}
@@ -56,6 +56,13 @@ impl RofiPluginState {
     }
 }
 
+// 单行的额外选项, 见 rofi-script(5)
+#[derive(Default)]
+pub struct RofiRowOptions {
+    // 图标名或者图片路径, 需要 rofi 开启 -show-icons
+    pub icon: Option<String>,
+}
+
 pub struct RofiPlugin {
     entrypoint: String,
 
@@ -160,6 +167,10 @@ impl RofiPlugin {
         println!("\x00message\x1f{}", msg);
     }
 
+    pub fn set_markup_rows(&self) {
+        println!("\x00markup-rows\x1ftrue");
+    }
+
     pub fn set_theme(&self, msg: &str) {
         println!("\x00theme\x1f{}", msg);
     }
@@ -191,13 +202,25 @@ impl RofiPlugin {
 
     pub fn add_menu_entry_with_params<
         F: Fn(&RofiPlugin, Vec<String>) -> anyhow::Result<()> + 'static,
+    >(
+        &self,
+        entry: &str,
+        callback: F,
+        params: Vec<String>,
+    ) {
+        self.add_menu_entry_with_options(entry, callback, params, RofiRowOptions::default());
+    }
+
+    pub fn add_menu_entry_with_options<
+        F: Fn(&RofiPlugin, Vec<String>) -> anyhow::Result<()> + 'static,
     >(
         &self,
         entry: &str,
         _callback: F,
         params: Vec<String>,
+        options: RofiRowOptions,
     ) {
-        println!(
+        let mut row = format!(
             "{}\x00info\x1f{}",
             entry.replace("\n", " "),
             serde_json::to_string(&RofiPluginState::new(
@@ -206,6 +229,10 @@ impl RofiPlugin {
             ))
             .unwrap()
         );
+        if let Some(icon) = options.icon {
+            row.push_str(&format!("\x1ficon\x1f{}", icon));
+        }
+        println!("{}", row);
     }
 
     pub fn add_menu_line(&self, line: &str) {
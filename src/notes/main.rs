@@ -10,20 +10,39 @@ struct Notes {
 }
 
 fn serialize_notes(notes: &Notes) -> anyhow::Result<()> {
-    file::storage_save_to_file(notes, "notes")
+    file::secure_storage_save_to_file(notes, "notes")
 }
 
-fn deserialize_notes() -> Notes {
-    match file::storage_restore_from_file("notes") {
-        Ok(x) => x,
-        Err(_) => Notes {
+fn deserialize_notes() -> anyhow::Result<Notes> {
+    file::ensure_unlocked()?;
+    match file::secure_storage_restore_from_file("notes") {
+        Ok(x) => Ok(x),
+        Err(err) if file::is_not_found(&err) => Ok(Notes {
             contents: BTreeMap::new(),
-        },
+        }),
+        // 解密或者解析失败的时候不能当成空的, 否则下次保存会覆盖掉原来的内容
+        Err(err) => Err(err),
     }
 }
 
 pub fn list_notes(rofi: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
-    let notes = deserialize_notes();
+    let encryption_config = file::encryption_config();
+    if file::is_locked() {
+        match encryption_config.key_source {
+            file::EncryptionKeySource::Passphrase if file::has_passphrase() => {
+                rofi.add_menu_entry_with_params("[unlock]", unlock_with_passphrase, vec![])
+            }
+            file::EncryptionKeySource::Passphrase => {
+                rofi.add_menu_entry_with_params("[set_passphrase]", set_passphrase, vec![])
+            }
+            file::EncryptionKeySource::SecretService => {
+                rofi.add_menu_entry("[unlock]", unlock_with_secret_service)
+            }
+        }
+        return Ok(());
+    }
+
+    let notes = deserialize_notes()?;
 
     rofi.add_menu_entry_with_params(
         "[add_from_clipboard]",
@@ -35,19 +54,43 @@ pub fn list_notes(rofi: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
         rofi.add_menu_entry_with_params(&format!("{k} {v}"), set_clipboard, vec![v.clone()])
     }
 
+    if encryption_config.enabled {
+        rofi.add_menu_entry("[lock]", lock);
+    }
+
+    Ok(())
+}
+
+fn unlock_with_passphrase(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    file::unlock(Some(&params[0]))
+}
+
+fn set_passphrase(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
+    file::set_passphrase(&params[0], &params[1])
+}
+
+fn unlock_with_secret_service(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
+    file::unlock(None)
+}
+
+fn lock(_: &RofiPlugin, _: Vec<String>) -> anyhow::Result<()> {
+    // 和剪贴板历史共用同一个密钥, clipd 那边也要一起锁上
+    // 先锁笔记, clipd 没有运行的时候也不影响笔记
+    file::lock()?;
+    clipboard::ClipdClient::new().lock()?;
     Ok(())
 }
 
 fn save_current_clipboard_text_to_notes(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
-    let clipboard_text = clipboard::clipboard_get_text();
+    let clipboard_text = clipboard::ClipdClient::new().get_latest_text()?;
 
-    let mut notes = deserialize_notes();
+    let mut notes = deserialize_notes()?;
     notes.contents.insert(params[0].clone(), clipboard_text);
     serialize_notes(&notes)
 }
 
 fn set_clipboard(_: &RofiPlugin, params: Vec<String>) -> anyhow::Result<()> {
-    clipboard::clipboard_set_text(&params[0]);
+    clipboard::ClipdClient::new().set_text(&params[0])?;
 
     Ok(())
 }
@@ -58,6 +101,16 @@ fn main() {
     rofi.register_entrypoint(list_notes);
 
     rofi.register_callback_with_params(set_clipboard, vec![String::from("content")]);
+    rofi.register_callback_with_params(unlock_with_passphrase, vec![String::from("passphrase")]);
+    rofi.register_callback_with_params(
+        set_passphrase,
+        vec![
+            String::from("passphrase"),
+            String::from("confirm passphrase"),
+        ],
+    );
+    rofi.register_callback(unlock_with_secret_service);
+    rofi.register_callback(lock);
     rofi.register_callback_with_params(
         save_current_clipboard_text_to_notes,
         vec![String::from("key")],